@@ -7,7 +7,7 @@ use std::{
 use tracing::{debug, info};
 
 use crate::{
-    client::{ClientId, GameClient},
+    client::{ClientHandle, ClientId, GameClient},
     events::ServerEvent,
     protocol::Protocol,
 };
@@ -45,7 +45,7 @@ impl Channel {
         }
     }
 
-    async fn send(protocol: Arc<Protocol>, msg: Arc<String>) {
+    pub async fn send(protocol: Arc<Protocol>, msg: Arc<String>) {
         match protocol.send(&msg).await {
             Ok(_) => (),
             Err(e) => {
@@ -79,6 +79,12 @@ impl ChannelCollection {
         }
     }
 
+    pub fn subscribe_handle(&self, address: ChannelAddress, client: &ClientHandle) {
+        if let Some(channel) = self.arena.read().expect("lock poisioned").get(address) {
+            channel.subscribe(client.id, client.protocol());
+        }
+    }
+
     pub fn unsubscribe(&self, address: ChannelAddress, client: ClientId) {
         if let Some(channel) = self.arena.read().expect("lock poisioned").get(address) {
             channel.remove(client);
@@ -0,0 +1,110 @@
+use std::{
+    collections::VecDeque,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    config::{CHAT_RATE_LIMIT, CHAT_RATE_WINDOW, MAX_CHAT_MESSAGE_LENGTH},
+    gameroom::NetworkPlayer,
+    gameteam::TeamIdentifier,
+};
+
+#[derive(Serialize, Clone)]
+pub struct ChatMessage {
+    pub player: NetworkPlayer,
+    pub content: String,
+    // Only set for team messages, which are not shown to other teams
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team: Option<TeamIdentifier>,
+    // Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+impl ChatMessage {
+    pub fn new(
+        player: NetworkPlayer,
+        content: &str,
+        team: Option<TeamIdentifier>,
+    ) -> Result<Self, ChatError> {
+        let content = content.trim();
+        if content.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if content.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+            return Err(ChatError::TooLong);
+        }
+        Ok(Self {
+            player,
+            content: content.to_owned(),
+            team,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_millis() as u64),
+        })
+    }
+
+    pub fn is_visible_to(&self, team: Option<TeamIdentifier>) -> bool {
+        self.team.is_none() || self.team == team
+    }
+}
+
+// Allows at most `CHAT_RATE_LIMIT` messages in any `CHAT_RATE_WINDOW`
+#[derive(Default)]
+pub struct RateLimiter {
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn try_send(&mut self) -> Result<(), ChatError> {
+        self.try_send_at(Instant::now())
+    }
+
+    fn try_send_at(&mut self, now: Instant) -> Result<(), ChatError> {
+        while matches!(self.sent.front(), Some(t) if now.duration_since(*t) >= CHAT_RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= CHAT_RATE_LIMIT {
+            return Err(ChatError::RateLimited);
+        }
+        self.sent.push_back(now);
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ChatError {
+    #[error("Chat is disabled in this room.")]
+    Disabled,
+    #[error("You are not in this room.")]
+    NotInRoom,
+    #[error("You are not in a team.")]
+    NoTeam,
+    #[error("Chat messages cannot be empty.")]
+    Empty,
+    #[error(
+        "Chat messages cannot be longer than {} characters.",
+        MAX_CHAT_MESSAGE_LENGTH
+    )]
+    TooLong,
+    #[error("You are sending messages too quickly.")]
+    RateLimited,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_rate_limit() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        for _ in 0..CHAT_RATE_LIMIT {
+            assert!(limiter.try_send_at(start).is_ok());
+        }
+        assert!(limiter.try_send_at(start).is_err());
+        assert!(limiter.try_send_at(start + CHAT_RATE_WINDOW).is_ok());
+    }
+}
@@ -1,10 +1,11 @@
 use std::io::ErrorKind;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use tracing::{debug, info};
 
-use crate::config::TEAMS;
+use crate::channel::Channel;
 use crate::events::{ClientEvent, ServerEvent};
+use crate::gamedata::ClaimError;
 use crate::gameroom::PlayerRef;
 use crate::protocol::{InitialClientState, Protocol};
 use crate::requests::{BaseRequest, CreateRoomResponse, Request, Response};
@@ -13,12 +14,81 @@ use crate::GlobalServer;
 
 pub type ClientId = u32;
 
+// Shared with the room, so that the server can detach clients from a room they are no longer in
+type Membership = Arc<Mutex<Option<PlayerRef>>>;
+
+// Handle to a connected client, kept by the room for each of its members
+#[derive(Clone)]
+pub struct ClientHandle {
+    pub id: ClientId,
+    protocol: Arc<Protocol>,
+    membership: Membership,
+}
+
+impl ClientHandle {
+    pub fn membership(&self) -> Option<PlayerRef> {
+        *self.membership.lock().expect("lock poisoned")
+    }
+
+    pub fn set_membership(&self, player: Option<PlayerRef>) {
+        *self.membership.lock().expect("lock poisoned") = player;
+    }
+
+    pub fn detach(&self) {
+        self.set_membership(None);
+    }
+
+    pub fn protocol(&self) -> Arc<Protocol> {
+        self.protocol.clone()
+    }
+
+    pub fn send(&self, event: ServerEvent) {
+        let message = serde_json::to_string(&event).expect("event serialization");
+        tokio::spawn(Channel::send(self.protocol.clone(), Arc::new(message)));
+    }
+
+    // Sends a last event before closing the connection from our side
+    pub fn send_and_close(&self, event: ServerEvent) {
+        let message = serde_json::to_string(&event).expect("event serialization");
+        let protocol = self.protocol.clone();
+        tokio::spawn(async move {
+            Channel::send(protocol.clone(), Arc::new(message)).await;
+            protocol.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+impl ClientHandle {
+    // A client on a local socket that nobody reads, for tests that need room members
+    pub async fn for_test(id: ClientId) -> Self {
+        use crate::{rest::auth::Authenticator, session::ResumeTokens};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let auth = Authenticator::new(reqwest::Client::new(), "http://localhost".parse().unwrap());
+        Self {
+            id,
+            protocol: Arc::new(Protocol::new(
+                socket,
+                Arc::new(auth),
+                Arc::new(ResumeTokens::new()),
+            )),
+            membership: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
 pub struct GameClient {
     id: ClientId,
     server: GlobalServer,
     protocol: Arc<Protocol>,
     identity: PlayerIdentity,
-    player_id: Option<PlayerRef>,
+    resume_expiry: Option<u64>,
+    player_id: Membership,
 }
 
 impl GameClient {
@@ -33,17 +103,28 @@ impl GameClient {
             server,
             protocol: Arc::new(protocol),
             identity: initial.identity,
-            player_id: initial.player,
+            resume_expiry: initial.resume_expiry,
+            player_id: Arc::new(Mutex::new(None)),
         }
     }
 
     pub async fn run(mut self) {
-        if let Some(player_ref) = self.player_id {
+        // Taking over the account's previous session may give this client a place in a room
+        let (reconnect, generation) = self.server.open_session(&self);
+        self.protocol
+            .handshake_success(&self.identity, reconnect, generation, self.resume_expiry)
+            .await;
+        if let Some(player_ref) = self.player_id() {
             self.server.resubscribe_client(&self, player_ref);
         }
 
         loop {
             let data = self.protocol.recv().await;
+            // Connections that were taken over by a new login don't get to run anything else
+            if self.protocol.is_closing() {
+                self.handle_disconnect();
+                break;
+            }
             match data {
                 Ok(text) => {
                     debug!("Received: {}", text);
@@ -57,6 +138,7 @@ impl GameClient {
                         let sent = self.protocol.send(&res_text).await;
                         if let Err(e) = sent {
                             self.protocol.error(&e.to_string()).await;
+                            self.handle_disconnect();
                             return;
                         }
                     } else {
@@ -81,19 +163,24 @@ impl GameClient {
         match variant {
             Request::Ping => Response::Pong,
             Request::CreateRoom(req) => {
+                if let Err(e) = req.config.validate() {
+                    return Response::Error {
+                        error: e.to_string(),
+                    };
+                }
                 let (player, name, join_code, teams) =
                     self.server.create_new_room(req.config.clone(), &self);
-                self.player_id = Some(player);
+                self.set_player_id(Some(player));
                 Response::CreateRoom(CreateRoomResponse {
                     name,
                     join_code,
                     teams,
-                    max_teams: TEAMS.len(),
+                    max_teams: req.config.max_teams,
                 })
             }
             Request::JoinRoom { join_code } => match self.server.join_room(&self, join_code) {
                 Ok((player, name, config, status)) => {
-                    self.player_id = Some(player);
+                    self.set_player_id(Some(player));
                     Response::JoinRoom {
                         name,
                         config: config,
@@ -105,46 +192,63 @@ impl GameClient {
                 },
             },
             Request::EditRoomConfig { config } => {
-                if let Some((room, _)) = self.player_id {
-                    self.server.edit_room_config(room, config.clone());
-                    return Response::Ok;
-                }
-                Response::Error {
-                    error: "You are not in a room.".to_owned(),
+                if let Err(e) = config.validate() {
+                    return Response::Error {
+                        error: e.to_string(),
+                    };
                 }
+                self.in_room(|player| self.server.edit_room_config(player, config.clone()))
             }
-            Request::CreateTeam => {
-                if let Some((room, _)) = self.player_id {
-                    self.server.add_team(room);
-                    return Response::Ok;
-                }
-                Response::Error {
-                    error: "You are not in a room.".to_owned(),
-                }
+            Request::CreateTeam { name, color } => self.in_room(|player| {
+                self.server
+                    .add_team(player, name.as_deref(), color.as_deref())
+            }),
+            Request::DeleteTeam { team_id, move_to } => {
+                self.in_room(|player| self.server.delete_team(player, *team_id, *move_to))
             }
-            Request::StartGame => {
+            Request::EditTeam {
+                team_id,
+                name,
+                color,
+            } => self.in_room(|player| {
                 self.server
-                    .start_game(self.player_id.expect("client is in a room"));
-                Response::Ok
+                    .edit_team(player, *team_id, name.as_deref(), color.as_deref())
+            }),
+            Request::StartGame => self.in_room(|player| self.server.start_game(player)),
+            Request::PauseGame => self.in_room(|player| self.server.pause_game(player)),
+            Request::ResumeGame => self.in_room(|player| self.server.resume_game(player)),
+            Request::Rematch => self.in_room(|player| self.server.rematch(player)),
+            Request::ReloadMaps => self.in_room(|player| self.server.reload_maps(player)),
+            Request::SetPlayerRole { account_id, role } => {
+                self.in_room(|player| self.server.set_player_role(player, account_id, *role))
             }
-            Request::ClaimCell { uid, time, medal } => {
-                if let Some(player) = self.player_id {
-                    self.server.claim_cell(player, uid.clone(), *time, *medal);
-                    return Response::Ok;
-                }
-                Response::Error {
-                    error: "You are not in a room.".to_owned(),
-                }
+            Request::KickPlayer { account_id } => {
+                self.in_room(|player| self.server.kick_player(player, account_id, false))
+            }
+            Request::BanPlayer { account_id } => {
+                self.in_room(|player| self.server.kick_player(player, account_id, true))
+            }
+            Request::CloseRoom => self.in_room(|player| self.server.close_room(player)),
+            Request::ClaimCell { uid, time, medal } => match self.player_id() {
+                Some(player) => match self.server.claim_cell(player, uid.clone(), *time, *medal) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => e.into(),
+                },
+                // Every rejected claim has a reason, including claims from outside a room
+                None => ClaimError::NotInRoom.into(),
+            },
+            Request::SendChat { message, team_only } => {
+                self.in_room(|player| self.server.send_chat(player, message, *team_only))
             }
             Request::Sync => {
-                if self.player_id.is_none() {
+                if self.player_id().is_none() {
                     return Response::Error {
                         error: "Sync failed, the game you joined may have ended already."
                             .to_string(),
                     };
                 }
-                match self.server.sync_client(self.player_id.unwrap()) {
-                    Some(sync) => Response::Sync(sync),
+                match self.server.sync_client(self.player_id().unwrap()) {
+                    Some(sync) => Response::Sync(Box::new(sync)),
                     None => Response::Error {
                         error: "Sync error".to_string(),
                     }, // TODO: handle results
@@ -153,15 +257,30 @@ impl GameClient {
         }
     }
 
+    // Runs a request that needs the client to be in a room
+    fn in_room<E: Into<Response>>(&self, f: impl FnOnce(PlayerRef) -> Result<(), E>) -> Response {
+        match self.player_id() {
+            Some(player) => match f(player) {
+                Ok(()) => Response::Ok,
+                Err(e) => e.into(),
+            },
+            None => Response::Error {
+                error: "You are not in a room.".to_owned(),
+            },
+        }
+    }
+
     async fn handle_event(&mut self, variant: &ClientEvent) {
         match variant {
             ClientEvent::ChangeTeam { team_id } => {
-                if let Some(player) = self.player_id {
-                    self.server.change_team(player.clone(), *team_id);
+                if let Some(player) = self.player_id() {
+                    if let Err(e) = self.server.change_team(player, *team_id) {
+                        debug!("Refused team change: {}", e);
+                    }
                 }
             }
             ClientEvent::LeaveRoom => {
-                if let Some(player) = self.player_id {
+                if let Some(player) = self.player_id() {
                     self.server.leave(self.id, player);
                 }
             }
@@ -171,9 +290,10 @@ impl GameClient {
     fn handle_disconnect(&mut self) {
         info!("Client disconnected: {}", self.identity.display_name);
         self.protocol.close();
-        if let Some(player) = self.player_id {
+        if let Some(player) = self.player_id() {
             self.server.disconnect(self.id, player);
         }
+        self.server.close_session(&self);
     }
 
     async fn fire_event(&mut self, event: ServerEvent) {
@@ -198,4 +318,20 @@ impl GameClient {
     pub fn get_id(&self) -> ClientId {
         self.id
     }
+
+    pub fn handle(&self) -> ClientHandle {
+        ClientHandle {
+            id: self.id,
+            protocol: self.protocol.clone(),
+            membership: self.player_id.clone(),
+        }
+    }
+
+    fn player_id(&self) -> Option<PlayerRef> {
+        *self.player_id.lock().expect("lock poisoned")
+    }
+
+    fn set_player_id(&self, player: Option<PlayerRef>) {
+        *self.player_id.lock().expect("lock poisoned") = player;
+    }
 }
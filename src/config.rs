@@ -60,11 +60,50 @@ pub const TEAMS: [(&'static str, &'static str); 6] = [
     ("Yellow", "FFFF00"),
 ];
 
+// Bounds and default of the team limit that hosts can set in the room configuration
+pub const MIN_TEAM_LIMIT: usize = 2;
+pub const MAX_TEAM_LIMIT: usize = 64;
+pub const DEFAULT_TEAM_LIMIT: usize = 16;
+
+// Custom team names and colors
+pub const MAX_TEAM_NAME_LENGTH: usize = 24;
+pub const MIN_TEAM_COLOR_DISTANCE: f64 = 60.;
+// Generated team colors must stay readable on the dark game interface
+pub const MIN_TEAM_COLOR_CONTRAST: f64 = 3.;
+
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 200;
+pub const CHAT_RATE_LIMIT: usize = 5;
+pub const CHAT_RATE_WINDOW: Duration = Duration::from_secs(10);
+pub const CHAT_HISTORY_LENGTH: usize = 50;
+
+pub const MIN_GRID_SIZE: u8 = 3;
+pub const MAX_GRID_SIZE: u8 = 8;
+
 pub const JOINCODE_LENGTH: u32 = 6;
 pub const JOINCODE_CHARS: [char; 10] = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
 
 pub const MAXIMUM_PACKET_SIZE: i32 = 2048;
 
+pub const GAME_START_COUNTDOWN: Duration = Duration::from_secs(3);
+// How long players who dropped out of a started game keep their place in the room,
+// hosts can choose a value up to the maximum in the room configuration
+pub const DEFAULT_RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(120);
+pub const MAX_RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+// How long a resume token handed out on login can be used to reconnect without Openplanet
+pub const RESUME_TOKEN_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+// Rooms where nothing happened for this long are closed
+pub const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+pub const ROOM_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Remaining times at which players are warned that the game is about to end
+pub const TIME_LIMIT_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(60),
+    Duration::from_secs(10),
+];
+
 pub const MXRANDOM_MAX_AUTHOR_TIME: i32 = Duration::from_secs(5 * 60).as_millis() as i32;
 
 pub mod routes {
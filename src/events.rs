@@ -1,7 +1,9 @@
 use crate::{
-    gamedata::{BingoLine, MapClaim},
+    chat::ChatMessage,
+    gamedata::{ClaimError, GameSummary, MapClaim},
     gamemap::GameMap,
-    gameroom::{RoomConfiguration, RoomStatus},
+    gameroom::{CloseReason, NetworkPlayer, RoomConfiguration, RoomState, RoomStatus},
+    session::DisplacedReason,
 };
 use serde::{Deserialize, Serialize};
 
@@ -16,10 +18,28 @@ pub enum ClientEvent {
 #[serde(tag = "event")]
 pub enum ServerEvent {
     RoomUpdate(RoomStatus),
+    HostChanged {
+        host: NetworkPlayer,
+    },
+    PresenceChange {
+        player: NetworkPlayer,
+    },
     RoomConfigUpdate(RoomConfiguration),
+    RoomClosed {
+        reason: CloseReason,
+    },
+    Kicked {
+        banned: bool,
+    },
+    Displaced {
+        reason: DisplacedReason,
+    },
     MapsLoadResult {
         error: Option<String>,
     },
+    RoomStateChange {
+        state: RoomState,
+    },
     GameStart {
         maps: Vec<GameMap>,
     },
@@ -27,8 +47,14 @@ pub enum ServerEvent {
         cell_id: usize,
         claim: MapClaim,
     },
-    AnnounceBingo {
-        #[serde(flatten)]
-        line: BingoLine,
+    CellClaimRefused {
+        cell_id: usize,
+        claim: MapClaim,
+        reason: ClaimError,
+    },
+    TimeLimitWarning {
+        seconds_left: u64,
     },
+    GameEnd(GameSummary),
+    ChatMessage(ChatMessage),
 }
@@ -1,121 +1,387 @@
-use std::time::Instant;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use serde::{Serialize, Serializer};
 use serde_repr::Serialize_repr;
+use thiserror::Error;
 
-use crate::gameroom::{Medal, NetworkPlayer};
+use crate::{
+    gameroom::{ClaimPolicy, Medal, NetworkPlayer, PlayMode, WinCondition},
+    gameteam::TeamIdentifier,
+};
 
 #[derive(Serialize, Clone)]
 pub struct ActiveGameData {
-    #[serde(serialize_with = "serialize_time")]
-    pub start_time: Instant,
-    pub cells: Vec<MapCell>,
+    #[serde(rename = "start_time")]
+    pub clock: GameClock,
+    cells: Vec<MapCell>,
+    #[serde(skip)]
+    shape: GridShape,
+    #[serde(skip)]
+    play_mode: PlayMode,
+    #[serde(skip)]
+    lines: Vec<LineState>,
+    #[serde(skip)]
+    owner_cells: HashMap<Owner, usize>,
+    #[serde(skip)]
+    owner_bingos: HashMap<Owner, usize>,
+    #[serde(skip)]
+    player_claims: HashMap<String, (NetworkPlayer, usize)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridShape {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl GridShape {
+    pub fn new(rows: usize, columns: usize) -> Self {
+        Self { rows, columns }
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.rows * self.columns
+    }
+
+    // Diagonals are only defined on square grids
+    pub fn has_diagonals(&self) -> bool {
+        self.rows == self.columns
+    }
+}
+
+// Ownership counters of a single row, column or diagonal
+#[derive(Clone)]
+struct LineState {
+    direction: Direction,
+    index: u32,
+    length: usize,
+    owners: HashMap<Owner, usize>,
+}
+
+impl LineState {
+    fn new(direction: Direction, index: u32, length: usize) -> Self {
+        Self {
+            direction,
+            index,
+            length,
+            owners: HashMap::new(),
+        }
+    }
 }
 
 impl ActiveGameData {
-    pub fn new(cell_count: usize) -> Self {
+    pub fn new(shape: GridShape, play_mode: PlayMode) -> Self {
+        let cell_count = shape.cell_count();
         let mut cells = Vec::with_capacity(cell_count);
         for _ in 0..cell_count {
             cells.push(MapCell { claim: None });
         }
+
+        // Lines are stored as: rows, then columns, then both diagonals if the grid has them
+        let mut lines = Vec::with_capacity(shape.rows + shape.columns + 2);
+        for i in 0..shape.rows {
+            lines.push(LineState::new(
+                Direction::Horizontal,
+                i as u32,
+                shape.columns,
+            ));
+        }
+        for i in 0..shape.columns {
+            lines.push(LineState::new(Direction::Vertical, i as u32, shape.rows));
+        }
+        if shape.has_diagonals() {
+            lines.push(LineState::new(Direction::Diagonal, 0, shape.rows));
+            lines.push(LineState::new(Direction::Diagonal, 1, shape.rows));
+        }
+
         Self {
-            start_time: Instant::now(),
+            clock: GameClock::new(),
             cells,
+            shape,
+            play_mode,
+            lines,
+            owner_cells: HashMap::new(),
+            owner_bingos: HashMap::new(),
+            player_claims: HashMap::new(),
         }
     }
 
-    pub fn check_for_bingos(&self, grid_size: usize) -> Vec<BingoLine> {
-        let mut bingos = Vec::new();
-        // Horizontal
-        for i in 0..grid_size {
-            let line = self.cells[i * grid_size..(i + 1) * grid_size]
-                .iter()
-                .take(grid_size);
+    pub fn cells(&self) -> &Vec<MapCell> {
+        &self.cells
+    }
 
-            let unique_team = iter_check_unique_team(line);
+    pub fn get_cell(&self, cell_id: usize) -> Option<&MapCell> {
+        self.cells.get(cell_id)
+    }
 
-            if let Some(team) = unique_team {
-                bingos.push(BingoLine {
-                    direction: Direction::Horizontal,
-                    index: i as u32,
-                    team,
-                });
-            }
+    // Returns: whether a claim would take the cell from another team or player
+    pub fn is_steal(&self, cell_id: usize, claim: &MapClaim) -> bool {
+        matches!(
+            self.cells.get(cell_id).and_then(|c| c.claim.as_ref()),
+            Some(current) if self.owner_of(current) != self.owner_of(claim)
+        )
+    }
+
+    // Returns: the indices in `lines` of every line passing through a cell
+    fn lines_through(&self, cell_id: usize) -> impl Iterator<Item = usize> {
+        let GridShape { rows, columns } = self.shape;
+        let (row, col) = (cell_id / columns, cell_id % columns);
+        let diagonals = self.shape.has_diagonals();
+        let diagonal = (diagonals && row == col).then_some(rows + columns);
+        let anti_diagonal = (diagonals && row + col == columns - 1).then_some(rows + columns + 1);
+        [Some(row), Some(rows + col), diagonal, anti_diagonal]
+            .into_iter()
+            .flatten()
+    }
+
+    fn owner_of(&self, claim: &MapClaim) -> Option<Owner> {
+        match self.play_mode {
+            PlayMode::Teams => claim.player.team.map(Owner::Team),
+            PlayMode::FreeForAll => Some(Owner::Player(claim.player.account_id.clone())),
         }
+    }
 
-        // Vertical
-        for i in 0..grid_size {
-            let mut iter = self.cells.iter();
+    // Replaces the claim on a cell, updating line ownership on the way.
+    // Returns: the lines that were completed by this claim
+    pub fn claim_cell(&mut self, cell_id: usize, claim: MapClaim) -> Vec<BingoLine> {
+        let old_owner = self.cells[cell_id]
+            .claim
+            .as_ref()
+            .and_then(|c| self.owner_of(c));
+        let new_owner = self.owner_of(&claim);
+        self.player_claims
+            .entry(claim.player.account_id.clone())
+            .or_insert_with(|| (claim.player.clone(), 0))
+            .1 += 1;
+        self.cells[cell_id].claim = Some(claim);
+        if old_owner == new_owner {
+            return Vec::new();
+        }
 
-            // Advance by i items to align column
-            for _ in 0..i {
-                iter.next();
+        let mut completed = Vec::new();
+        for line_id in self.lines_through(cell_id).collect::<Vec<usize>>() {
+            let line = &mut self.lines[line_id];
+            let size = line.length;
+            if let Some(owner) = &old_owner {
+                let count = line
+                    .owners
+                    .get_mut(owner)
+                    .expect("line owner to be counted");
+                if *count == size {
+                    *self
+                        .owner_bingos
+                        .get_mut(owner)
+                        .expect("bingo to be counted") -= 1;
+                }
+                *count -= 1;
+            }
+            if let Some(owner) = &new_owner {
+                let count = line.owners.entry(owner.clone()).or_default();
+                *count += 1;
+                if *count == size {
+                    *self.owner_bingos.entry(owner.clone()).or_default() += 1;
+                    completed.push(BingoLine {
+                        direction: line.direction,
+                        index: line.index,
+                        owner: owner.clone(),
+                    });
+                }
             }
+        }
 
-            let col = iter.step_by(grid_size);
-            let unique_team = iter_check_unique_team(col);
+        if let Some(owner) = &old_owner {
+            *self
+                .owner_cells
+                .get_mut(owner)
+                .expect("owner cells to be counted") -= 1;
+        }
+        if let Some(owner) = new_owner {
+            *self.owner_cells.entry(owner).or_default() += 1;
+        }
+        completed
+    }
+
+    pub fn bingos(&self) -> Vec<BingoLine> {
+        self.lines
+            .iter()
+            .filter_map(|line| {
+                line.owners
+                    .iter()
+                    .find(|(_, count)| **count == line.length)
+                    .map(|(owner, _)| BingoLine {
+                        direction: line.direction,
+                        index: line.index,
+                        owner: owner.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    pub fn check_win(&self, condition: WinCondition, line_count: u32) -> Option<Victory> {
+        match condition {
+            WinCondition::Lines => {
+                let required = line_count.max(1) as usize;
+                let (owner, _) = self
+                    .owner_bingos
+                    .iter()
+                    .find(|(_, count)| **count >= required)?;
+                let lines = self
+                    .bingos()
+                    .into_iter()
+                    .filter(|line| &line.owner == owner)
+                    .collect();
+                Some(Victory {
+                    owner: owner.clone(),
+                    lines,
+                })
+            }
+            WinCondition::Blackout => self
+                .owner_cells
+                .iter()
+                .find(|(_, count)| **count == self.cells.len())
+                .map(|(owner, _)| Victory {
+                    owner: owner.clone(),
+                    lines: Vec::new(),
+                }),
+            WinCondition::MostCells => {
+                if self.cells.iter().any(|c| c.claim.is_none()) {
+                    return None;
+                }
+                self.leader().map(|owner| Victory {
+                    owner,
+                    lines: Vec::new(),
+                })
+            }
+        }
+    }
 
-            if let Some(team) = unique_team {
-                bingos.push(BingoLine {
-                    direction: Direction::Vertical,
-                    index: i as u32,
+    pub fn team_scores(&self) -> Vec<TeamScore> {
+        if self.play_mode == PlayMode::FreeForAll {
+            return Vec::new();
+        }
+        let mut scores: HashMap<TeamIdentifier, TeamScore> = HashMap::new();
+        for (owner, &cells) in self.owner_cells.iter().filter(|(_, cells)| **cells > 0) {
+            let &Owner::Team(team) = owner else {
+                continue;
+            };
+            scores.insert(
+                team,
+                TeamScore {
                     team,
-                });
+                    cells,
+                    claims: 0,
+                },
+            );
+        }
+        for (player, claims) in self.player_claims.values() {
+            if let Some(team) = player.team {
+                scores
+                    .entry(team)
+                    .or_insert(TeamScore {
+                        team,
+                        cells: 0,
+                        claims: 0,
+                    })
+                    .claims += claims;
             }
         }
 
-        // Diagonal
-        let mut diag0 = Vec::with_capacity(grid_size);
-        let mut diag1 = Vec::with_capacity(grid_size);
+        let mut scores: Vec<TeamScore> = scores.into_values().collect();
+        scores.sort_by(|a, b| b.cells.cmp(&a.cells).then(a.team.cmp(&b.team)));
+        scores
+    }
 
-        for i in 0..grid_size {
-            diag0.push(self.cells.get(i * grid_size + i).unwrap());
-            diag1.push(self.cells.get((grid_size - 1) * (i + 1)).unwrap());
-        }
+    pub fn player_scores(&self) -> Vec<PlayerScore> {
+        let mut scores: Vec<PlayerScore> = self
+            .player_claims
+            .values()
+            .map(|(player, claims)| PlayerScore {
+                player: player.clone(),
+                cells: self
+                    .cells
+                    .iter()
+                    .filter_map(|c| c.claim.as_ref())
+                    .filter(|c| c.player.account_id == player.account_id)
+                    .count(),
+                claims: *claims,
+            })
+            .collect();
+        scores.sort_by(|a, b| b.cells.cmp(&a.cells).then(b.claims.cmp(&a.claims)));
+        scores
+    }
 
-        let unique_team0 = iter_check_unique_team(diag0.into_iter());
-        let unique_team1 = iter_check_unique_team(diag1.into_iter());
+    pub fn summary(&self, victory: Option<Victory>) -> GameSummary {
+        let (winner, lines) = match victory {
+            Some(victory) => (Some(victory.owner), victory.lines),
+            None => (None, Vec::new()),
+        };
+        GameSummary {
+            winner,
+            lines,
+            cells: self.cells.clone(),
+            teams: self.team_scores(),
+            players: self.player_scores(),
+            duration: self.clock.elapsed(),
+        }
+    }
 
-        if let Some(team) = unique_team0 {
-            bingos.push(BingoLine {
-                direction: Direction::Diagonal,
-                index: 0,
-                team,
-            });
+    // Returns: the team or player owning the most cells, or None if nobody is strictly ahead
+    pub fn leader(&self) -> Option<Owner> {
+        let mut scores: Vec<(&Owner, usize)> = self
+            .owner_cells
+            .iter()
+            .map(|(owner, cells)| (owner, *cells))
+            .filter(|(_, cells)| *cells > 0)
+            .collect();
+        scores.sort_by_key(|(_, cells)| Reverse(*cells));
+        match scores.as_slice() {
+            [first, second, ..] if first.1 == second.1 => None,
+            [(owner, _), ..] => Some((*owner).clone()),
+            [] => None,
         }
+    }
+}
 
-        if let Some(team) = unique_team1 {
-            bingos.push(BingoLine {
-                direction: Direction::Diagonal,
-                index: 1,
-                team,
-            });
+// Measures the time spent playing, excluding pauses
+#[derive(Clone, Copy)]
+pub struct GameClock {
+    start_time: Instant,
+    paused_at: Option<Instant>,
+}
+
+impl GameClock {
+    fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            paused_at: None,
         }
+    }
 
-        bingos
+    pub fn elapsed(&self) -> Duration {
+        self.paused_at
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.start_time)
     }
-}
 
-fn iter_check_unique_team<'a>(mut iter: impl Iterator<Item = &'a MapCell>) -> Option<usize> {
-    let first = iter
-        .next()
-        .expect("invalid grid_size")
-        .claim
-        .as_ref()
-        .and_then(|c| c.player.team);
-    iter.fold(first, |acc, x| {
-        acc.and_then(|y| {
-            if x.claim.as_ref().and_then(|c| c.player.team) == Some(y) {
-                Some(y)
-            } else {
-                None
-            }
-        })
-    })
+    pub fn pause(&mut self) {
+        self.paused_at.get_or_insert_with(Instant::now);
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.start_time += paused_at.elapsed();
+        }
+    }
 }
 
-fn serialize_time<S: Serializer>(time: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
-    serializer.serialize_u128(time.elapsed().as_millis())
+impl Serialize for GameClock {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u128(self.elapsed().as_millis())
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -130,14 +396,91 @@ pub struct MapClaim {
     pub medal: Medal,
 }
 
+impl MapClaim {
+    // Checks whether this claim is allowed to replace the current one under a claim policy
+    pub fn can_replace(&self, current: &MapClaim, policy: ClaimPolicy) -> Result<(), ClaimError> {
+        match policy {
+            ClaimPolicy::StealByTime if self.time >= current.time => Err(ClaimError::NotImproved),
+            ClaimPolicy::Lockout => Err(ClaimError::CellLocked),
+            ClaimPolicy::StealByMedal if !self.medal.is_better_than(current.medal) => {
+                Err(ClaimError::MedalNotImproved)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Error, Serialize, Debug, Clone, Copy)]
+pub enum ClaimError {
+    #[error("The game is not running.")]
+    NotRunning,
+    #[error("You are not in this room.")]
+    NotInRoom,
+    #[error("This map is not part of the grid.")]
+    UnknownMap,
+    #[error("Your medal does not meet the room's requirement.")]
+    MedalRequirement,
+    #[error("This time does not earn the claimed medal.")]
+    InvalidTime,
+    #[error("Your time does not beat the current claim.")]
+    NotImproved,
+    #[error("This cell has already been claimed.")]
+    CellLocked,
+    #[error("A better medal is required to steal this cell.")]
+    MedalNotImproved,
+}
+
+// Who cells and lines are counted for: a team, or a single player in free-for-all rooms
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Owner {
+    Team(TeamIdentifier),
+    Player(String),
+}
+
+pub struct Victory {
+    pub owner: Owner,
+    pub lines: Vec<BingoLine>,
+}
+
 #[derive(Serialize, Clone)]
+pub struct TeamScore {
+    pub team: TeamIdentifier,
+    pub cells: usize,
+    pub claims: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlayerScore {
+    pub player: NetworkPlayer,
+    pub cells: usize,
+    pub claims: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GameSummary {
+    pub winner: Option<Owner>,
+    pub lines: Vec<BingoLine>,
+    pub cells: Vec<MapCell>,
+    pub teams: Vec<TeamScore>,
+    pub players: Vec<PlayerScore>,
+    #[serde(serialize_with = "serialize_duration")]
+    pub duration: Duration,
+}
+
+fn serialize_duration<S: Serializer>(time: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(time.as_millis())
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct BingoLine {
     pub direction: Direction,
     pub index: u32,
-    pub team: usize,
+    #[serde(flatten)]
+    pub owner: Owner,
 }
 
-#[derive(Serialize_repr, Clone, Copy)]
+#[derive(Serialize_repr, Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum Direction {
     None = 0,
@@ -145,3 +488,239 @@ pub enum Direction {
     Vertical = 2,
     Diagonal = 3,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::{MAX_GRID_SIZE, MIN_GRID_SIZE},
+        gameroom::PlayerRole,
+    };
+
+    fn claim_for(team: TeamIdentifier) -> MapClaim {
+        MapClaim {
+            player: NetworkPlayer {
+                account_id: format!("account-{}", team),
+                name: format!("Player {}", team),
+                team: Some(team),
+                role: PlayerRole::Member,
+                disconnected: false,
+            },
+            time: 10000,
+            medal: Medal::Author,
+        }
+    }
+
+    fn grid_with_claims(shape: GridShape, claims: &[(usize, TeamIdentifier)]) -> ActiveGameData {
+        let mut game = ActiveGameData::new(shape, PlayMode::Teams);
+        for (cell, team) in claims {
+            game.claim_cell(*cell, claim_for(*team));
+        }
+        game
+    }
+
+    fn square(size: usize) -> GridShape {
+        GridShape::new(size, size)
+    }
+
+    // Every grid shape allowed by the room configuration
+    fn all_shapes() -> Vec<GridShape> {
+        let sizes = MIN_GRID_SIZE as usize..=MAX_GRID_SIZE as usize;
+        sizes
+            .clone()
+            .flat_map(|rows| {
+                sizes
+                    .clone()
+                    .map(move |columns| GridShape::new(rows, columns))
+            })
+            .collect()
+    }
+
+    // Cells of every line of a grid, in the order they are stored
+    fn line_cells(shape: GridShape) -> Vec<(Direction, u32, Vec<usize>)> {
+        let GridShape { rows, columns } = shape;
+        let mut lines = Vec::new();
+        for i in 0..rows {
+            let row = (0..columns).map(|j| i * columns + j).collect();
+            lines.push((Direction::Horizontal, i as u32, row));
+        }
+        for i in 0..columns {
+            let col = (0..rows).map(|j| j * columns + i).collect();
+            lines.push((Direction::Vertical, i as u32, col));
+        }
+        if shape.has_diagonals() {
+            let size = rows;
+            lines.push((
+                Direction::Diagonal,
+                0,
+                (0..size).map(|i| i * size + i).collect(),
+            ));
+            lines.push((
+                Direction::Diagonal,
+                1,
+                (0..size).map(|i| i * size + (size - 1 - i)).collect(),
+            ));
+        }
+        lines
+    }
+
+    #[test]
+    fn check_every_line_completes() {
+        for shape in all_shapes() {
+            for (direction, index, cells) in line_cells(shape) {
+                let mut game = ActiveGameData::new(shape, PlayMode::Teams);
+                let (last, rest) = cells.split_last().unwrap();
+                for cell in rest {
+                    assert!(game.claim_cell(*cell, claim_for(0)).is_empty());
+                }
+
+                let expected = BingoLine {
+                    direction,
+                    index,
+                    owner: Owner::Team(0),
+                };
+                assert_eq!(game.claim_cell(*last, claim_for(0)), vec![expected.clone()]);
+                assert_eq!(game.bingos(), vec![expected]);
+            }
+        }
+    }
+
+    #[test]
+    fn check_stolen_cell_breaks_line() {
+        for shape in all_shapes() {
+            for (_, _, cells) in line_cells(shape) {
+                let claims: Vec<(usize, TeamIdentifier)> = cells.iter().map(|c| (*c, 0)).collect();
+                let mut game = grid_with_claims(shape, &claims);
+                assert!(game.check_win(WinCondition::Lines, 1).is_some());
+
+                game.claim_cell(cells[cells.len() / 2], claim_for(1));
+                assert!(game.bingos().is_empty());
+                assert!(game.check_win(WinCondition::Lines, 1).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn check_center_cell_completes_four_lines() {
+        let shape = square(5);
+        let claims: Vec<(usize, TeamIdentifier)> = line_cells(shape)
+            .into_iter()
+            .filter(|(_, _, cells)| cells.contains(&12))
+            .flat_map(|(_, _, cells)| cells)
+            .filter(|c| *c != 12)
+            .map(|c| (c, 0))
+            .collect();
+        let mut game = grid_with_claims(shape, &claims);
+        assert!(game.bingos().is_empty());
+        assert_eq!(game.claim_cell(12, claim_for(0)).len(), 4);
+    }
+
+    #[test]
+    fn check_strip_has_no_diagonals() {
+        let shape = GridShape::new(3, 7);
+        assert_eq!(line_cells(shape).len(), 10);
+
+        // The top-left to bottom-right cells of a square would make a diagonal here
+        let game = grid_with_claims(shape, &[(0, 0), (8, 0), (16, 0)]);
+        assert!(game.bingos().is_empty());
+    }
+
+    #[test]
+    fn check_claim_policies() {
+        let current = claim_for(0);
+        let mut faster = claim_for(1);
+        faster.time -= 1;
+        let mut better_medal = claim_for(1);
+        better_medal.medal = Medal::Author;
+        let mut current_gold = current.clone();
+        current_gold.medal = Medal::Gold;
+
+        assert!(faster
+            .can_replace(&current, ClaimPolicy::StealByTime)
+            .is_ok());
+        assert!(current
+            .can_replace(&current, ClaimPolicy::StealByTime)
+            .is_err());
+        assert!(faster.can_replace(&current, ClaimPolicy::Lockout).is_err());
+        assert!(faster
+            .can_replace(&current, ClaimPolicy::StealByMedal)
+            .is_err());
+        assert!(better_medal
+            .can_replace(&current_gold, ClaimPolicy::StealByMedal)
+            .is_ok());
+    }
+
+    #[test]
+    fn check_steal_detection() {
+        let mut game = grid_with_claims(square(3), &[(0, 0)]);
+        assert!(!game.is_steal(0, &claim_for(0)));
+        assert!(game.is_steal(0, &claim_for(1)));
+        assert!(!game.is_steal(1, &claim_for(1)));
+
+        // Players of the same team own the cell together, unlike in free-for-all
+        let mut teammate = claim_for(0);
+        teammate.player.account_id = "account-teammate".to_owned();
+        assert!(!game.is_steal(0, &teammate));
+        game.play_mode = PlayMode::FreeForAll;
+        assert!(game.is_steal(0, &teammate));
+    }
+
+    #[test]
+    fn check_win_two_lines() {
+        // First row and first column for team 0
+        let game = grid_with_claims(square(3), &[(0, 0), (1, 0), (2, 0), (3, 0), (6, 1)]);
+        assert!(game.check_win(WinCondition::Lines, 1).is_some());
+        assert!(game.check_win(WinCondition::Lines, 2).is_none());
+
+        let game = grid_with_claims(square(3), &[(0, 0), (1, 0), (2, 0), (3, 0), (6, 0)]);
+        let victory = game.check_win(WinCondition::Lines, 2).unwrap();
+        assert_eq!(victory.owner, Owner::Team(0));
+        assert_eq!(victory.lines.len(), 2);
+    }
+
+    #[test]
+    fn check_win_blackout() {
+        let mut claims: Vec<(usize, TeamIdentifier)> = (0..9).map(|i| (i, 1)).collect();
+        let game = grid_with_claims(square(3), &claims);
+        assert_eq!(
+            game.check_win(WinCondition::Blackout, 1).unwrap().owner,
+            Owner::Team(1)
+        );
+
+        claims[4].1 = 0;
+        let game = grid_with_claims(square(3), &claims);
+        assert!(game.check_win(WinCondition::Blackout, 1).is_none());
+    }
+
+    #[test]
+    fn check_win_most_cells() {
+        let mut claims: Vec<(usize, TeamIdentifier)> = (0..8).map(|i| (i, i % 2)).collect();
+        let game = grid_with_claims(square(3), &claims);
+        assert!(game.check_win(WinCondition::MostCells, 1).is_none());
+
+        claims.push((8, 1));
+        let game = grid_with_claims(square(3), &claims);
+        assert_eq!(
+            game.check_win(WinCondition::MostCells, 1).unwrap().owner,
+            Owner::Team(1)
+        );
+    }
+
+    #[test]
+    fn check_free_for_all_lines() {
+        let mut game = ActiveGameData::new(square(3), PlayMode::FreeForAll);
+        let mut teammate = claim_for(0);
+        teammate.player.account_id = "account-teammate".to_owned();
+
+        // Players of the same team do not share lines
+        game.claim_cell(0, claim_for(0));
+        game.claim_cell(1, claim_for(0));
+        game.claim_cell(2, teammate.clone());
+        assert!(game.bingos().is_empty());
+
+        game.claim_cell(2, claim_for(0));
+        let victory = game.check_win(WinCondition::Lines, 1).unwrap();
+        assert_eq!(victory.owner, Owner::Player("account-0".to_owned()));
+        assert!(game.team_scores().is_empty());
+    }
+}
@@ -14,7 +14,7 @@ use tracing::{info, warn};
 
 use crate::{
     config,
-    gameroom::MapMode,
+    gameroom::{MapMode, Medal},
     rest::tmexchange::{get_mappack_tracks, get_randomtmx, get_totd},
 };
 
@@ -221,6 +221,24 @@ pub struct GameMap {
     pub uid: String,
     pub name: String,
     pub author_name: String,
+    // Medal times are None when TMX does not know them
+    pub author_time: Option<u64>,
+    pub gold_time: Option<u64>,
+    pub silver_time: Option<u64>,
+    pub bronze_time: Option<u64>,
+}
+
+impl GameMap {
+    // Returns: the slowest time that earns a medal, or None if any time is accepted
+    pub fn medal_time(&self, medal: Medal) -> Option<u64> {
+        match medal {
+            Medal::Author => self.author_time,
+            Medal::Gold => self.gold_time,
+            Medal::Silver => self.silver_time,
+            Medal::Bronze => self.bronze_time,
+            Medal::None => None,
+        }
+    }
 }
 
 pub struct MapQuery {
@@ -1,18 +1,28 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
 use generational_arena::Arena;
-use rand::Rng;
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use thiserror::Error;
-use tracing::warn;
 
 use crate::{
     channel::ChannelAddress,
-    client::GameClient,
-    config::TEAMS,
-    gamedata::{ActiveGameData, BingoLine, MapCell},
+    chat::{ChatError, ChatMessage, RateLimiter},
+    client::{ClientHandle, GameClient},
+    config::{
+        CHAT_HISTORY_LENGTH, DEFAULT_RECONNECT_GRACE_PERIOD, DEFAULT_TEAM_LIMIT, MAX_GRID_SIZE,
+        MAX_RECONNECT_GRACE_PERIOD, MAX_TEAM_LIMIT, MIN_GRID_SIZE, MIN_TEAM_COLOR_CONTRAST,
+        MIN_TEAM_COLOR_DISTANCE, MIN_TEAM_LIMIT, ROOM_IDLE_TIMEOUT, TEAMS,
+    },
+    gamedata::{ActiveGameData, BingoLine, GridShape, MapCell, MapClaim, Owner, Victory},
     gamemap::GameMap,
-    gameteam::{GameTeam, TeamIdentifier},
+    gameteam::{parse_team_color, parse_team_name, GameTeam, TeamError, TeamIdentifier},
     rest::auth::PlayerIdentity,
+    util::color::{generate_distinct, RgbColor},
 };
 
 pub type RoomIdentifier = generational_arena::Index;
@@ -27,7 +37,14 @@ pub struct GameRoom {
     teams: Vec<GameTeam>,
     channel: ChannelAddress,
     maps: Vec<GameMap>,
+    state: RoomState,
+    round: u32,
     active: Option<ActiveGameData>,
+    join_count: u32,
+    banned: HashSet<String>,
+    next_team_id: TeamIdentifier,
+    chat: VecDeque<ChatMessage>,
+    last_activity: Instant,
 }
 
 impl GameRoom {
@@ -45,7 +62,14 @@ impl GameRoom {
             teams: Vec::new(),
             channel,
             maps: Vec::new(),
+            state: RoomState::Lobby,
+            round: 0,
             active: None,
+            join_count: 0,
+            banned: HashSet::new(),
+            next_team_id: 0,
+            chat: VecDeque::new(),
+            last_activity: Instant::now(),
         }
     }
 
@@ -73,8 +97,24 @@ impl GameRoom {
         &self.active
     }
 
+    pub fn game_data_mut(&mut self) -> Option<&mut ActiveGameData> {
+        self.active.as_mut()
+    }
+
+    pub fn state(&self) -> RoomState {
+        self.state
+    }
+
+    // Number of games that were started in this room, counted from their countdown
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
     pub fn has_started(&self) -> bool {
-        self.active.is_some()
+        matches!(
+            self.state,
+            RoomState::Countdown | RoomState::Running | RoomState::Paused
+        )
     }
 
     pub fn add_maps(&mut self, maps: Vec<GameMap>) {
@@ -115,103 +155,486 @@ impl GameRoom {
         self.members.get(player)
     }
 
-    pub fn get_team(&self, player: TeamIdentifier) -> Option<&GameTeam> {
-        self.teams.get(player)
+    pub fn members(&self) -> impl Iterator<Item = &PlayerData> {
+        self.members.iter().map(|(_, player)| player)
     }
 
-    pub fn create_team(&mut self, channel: ChannelAddress) -> Option<&GameTeam> {
-        let team_count = self.teams.len();
-        if team_count >= TEAMS.len() {
-            // FIXME avoiding panic here
-            // panic!("attempted to create more than {} teams", TEAMS.len());
-            warn!("attempted to create more than {} teams", TEAMS.len());
-            return None;
+    pub fn get_team(&self, team: TeamIdentifier) -> Option<&GameTeam> {
+        self.teams.iter().find(|t| t.id == team)
+    }
+
+    // Missing names or colors are taken from a random preset that fits with the existing teams,
+    // and generated once no preset fits anymore
+    pub fn create_team(
+        &mut self,
+        channel: ChannelAddress,
+        name: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<&GameTeam, TeamError> {
+        if self.teams.len() >= self.config.max_teams {
+            return Err(TeamError::LimitReached);
+        }
+
+        let name = name.map(parse_team_name).transpose()?;
+        let color = color.map(parse_team_color).transpose()?;
+        let (name, color) = match (name, color) {
+            (Some(name), Some(color)) => (name, color),
+            (name, color) => match self.preset_style(&name, color) {
+                Some(style) => style,
+                None => self.generated_style(name, color)?,
+            },
+        };
+        self.check_team_style(None, &name, color)?;
+
+        self.touch();
+        // Identifiers are never reused, so that they stay stable when teams are deleted
+        self.next_team_id += 1;
+        self.teams
+            .push(GameTeam::new(self.next_team_id - 1, name, color, channel));
+        Ok(self.teams.last().expect("team was just created"))
+    }
+
+    fn preset_style(
+        &self,
+        name: &Option<String>,
+        color: Option<RgbColor>,
+    ) -> Option<(String, RgbColor)> {
+        let presets: Vec<(String, RgbColor)> = TEAMS
+            .iter()
+            .map(|(preset_name, preset_color)| {
+                (
+                    name.clone().unwrap_or(preset_name.to_string()),
+                    color.unwrap_or(RgbColor::from_hex(preset_color).expect("team color parsing")),
+                )
+            })
+            .filter(|(name, color)| self.check_team_style(None, name, *color).is_ok())
+            .collect();
+        presets.choose(&mut rand::thread_rng()).cloned()
+    }
+
+    fn generated_style(
+        &self,
+        name: Option<String>,
+        color: Option<RgbColor>,
+    ) -> Result<(String, RgbColor), TeamError> {
+        let name = name.unwrap_or_else(|| {
+            (self.teams.len() + 1..)
+                .map(|n| format!("Team {}", n))
+                .find(|name| {
+                    !self
+                        .teams
+                        .iter()
+                        .any(|t| t.name.to_lowercase() == name.to_lowercase())
+                })
+                .expect("a free team name")
+        });
+        let color = match color {
+            Some(color) => color,
+            None => {
+                let existing: Vec<RgbColor> = self.teams.iter().map(|t| t.color).collect();
+                generate_distinct(
+                    &existing,
+                    MIN_TEAM_COLOR_DISTANCE,
+                    RgbColor(0, 0, 0),
+                    MIN_TEAM_COLOR_CONTRAST,
+                )
+                .ok_or(TeamError::NoStyleAvailable)?
+            }
+        };
+        Ok((name, color))
+    }
+
+    pub fn edit_team(
+        &mut self,
+        team: TeamIdentifier,
+        name: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<(), TeamError> {
+        let current = self.get_team(team).ok_or(TeamError::NotFound)?;
+        let name = match name {
+            Some(name) => parse_team_name(name)?,
+            None => current.name.clone(),
+        };
+        let color = match color {
+            Some(color) => parse_team_color(color)?,
+            None => current.color,
+        };
+        self.check_team_style(Some(team), &name, color)?;
+        self.touch();
+
+        let team = self
+            .teams
+            .iter_mut()
+            .find(|t| t.id == team)
+            .expect("team exists");
+        team.name = name;
+        team.color = color;
+        Ok(())
+    }
+
+    // Players of the deleted team are moved to another team, or left without a team
+    pub fn delete_team(
+        &mut self,
+        team: TeamIdentifier,
+        move_to: Option<TeamIdentifier>,
+    ) -> Result<GameTeam, TeamError> {
+        let index = self
+            .teams
+            .iter()
+            .position(|t| t.id == team)
+            .ok_or(TeamError::NotFound)?;
+        if self.teams.len() == 1 {
+            return Err(TeamError::LastTeam);
+        }
+        if matches!(move_to, Some(target) if target == team || !self.team_exsits(target)) {
+            return Err(TeamError::NotFound);
         }
 
-        let mut rng = rand::thread_rng();
-        let mut idx = rng.gen_range(0..TEAMS.len());
-        while self.team_exsits_with_index(idx) {
-            idx = rng.gen_range(0..TEAMS.len());
+        self.touch();
+        for (_, player) in self.members.iter_mut() {
+            if player.team == Some(team) {
+                player.team = move_to;
+            }
         }
+        Ok(self.teams.remove(index))
+    }
 
-        self.teams.push(GameTeam::new(team_count, idx, channel));
-        self.teams.last()
+    // Teams must stay distinguishable from each other, by name and by color
+    fn check_team_style(
+        &self,
+        except: Option<TeamIdentifier>,
+        name: &str,
+        color: RgbColor,
+    ) -> Result<(), TeamError> {
+        for team in self.teams.iter().filter(|t| Some(t.id) != except) {
+            if team.name.to_lowercase() == name.to_lowercase() {
+                return Err(TeamError::DuplicateName(team.name.clone()));
+            }
+            if team.color.distance(&color) < MIN_TEAM_COLOR_DISTANCE {
+                return Err(TeamError::SimilarColor(team.name.clone()));
+            }
+        }
+        Ok(())
     }
 
     fn team_exsits(&self, id: usize) -> bool {
         self.teams.iter().any(|t| t.id == id)
     }
 
-    fn team_exsits_with_index(&self, idx: usize) -> bool {
-        self.teams.iter().any(|t| t.gen_index == idx)
+    // The team with the fewest players, ties going to the oldest team
+    fn smallest_team(&self) -> Option<TeamIdentifier> {
+        self.teams
+            .iter()
+            .min_by_key(|team| {
+                self.members
+                    .iter()
+                    .filter(|(_, p)| p.team == Some(team.id))
+                    .count()
+            })
+            .map(|team| team.id)
+    }
+
+    // Deals players into teams in a random order, so that team sizes differ by one at most
+    pub fn shuffle_teams(&mut self) {
+        if self.teams.is_empty() {
+            return;
+        }
+        let mut players: Vec<PlayerIdentifier> = self.members.iter().map(|(id, _)| id).collect();
+        players.shuffle(&mut rand::thread_rng());
+        for (i, player) in players.into_iter().enumerate() {
+            self.members[player].team = Some(self.teams[i % self.teams.len()].id);
+        }
     }
 
-    fn add_player(&mut self, client: &GameClient, operator: bool) -> PlayerIdentifier {
-        let team = if !self.config.randomize {
-            Some(0) // TODO: sort players in teams upon join
+    fn add_player(
+        &mut self,
+        identity: PlayerIdentity,
+        client: ClientHandle,
+        role: PlayerRole,
+    ) -> PlayerIdentifier {
+        self.touch();
+        // With randomized teams, players are assigned when the game starts
+        let team = if !self.config.randomize && self.config.play_mode == PlayMode::Teams {
+            self.smallest_team()
         } else {
             None
         };
+        self.join_count += 1;
         self.members.insert(PlayerData {
-            identity: client.identity().clone(),
+            identity,
             team,
-            operator,
-            disconnected: false,
+            role,
+            disconnected_at: None,
+            join_order: self.join_count,
+            client,
+            chat_limiter: RateLimiter::default(),
         })
     }
 
-    pub fn identify_player(&self, identity: &PlayerIdentity) -> Option<PlayerIdentifier> {
-        self.members
-            .iter()
-            .filter(|(_, p)| &p.identity == identity)
-            .next()
-            .map(|(id, _)| id)
-    }
-
     pub fn player_join(
         &mut self,
         client: &GameClient,
-        operator: bool,
+        role: PlayerRole,
     ) -> Result<PlayerIdentifier, JoinRoomError> {
-        if self.has_started() {
+        if self.banned.contains(&client.identity().account_id) {
+            return Err(JoinRoomError::Banned);
+        }
+        if !matches!(self.state, RoomState::Lobby | RoomState::LoadingMaps) {
             return Err(JoinRoomError::HasStarted);
         }
         if self.config.size != 0 && self.members.len() as u32 >= self.config.size {
             return Err(JoinRoomError::PlayerLimitReached);
         }
-        Ok(self.add_player(client, operator))
+        Ok(self.add_player(client.identity().clone(), client.handle(), role))
+    }
+
+    // Returns: the new operator, if the removed player was the operator and someone is left
+    pub fn player_remove(&mut self, player: PlayerIdentifier) -> Option<NetworkPlayer> {
+        self.touch();
+        let removed = self.members.remove(player)?;
+        if removed.role != PlayerRole::Operator {
+            return None;
+        }
+
+        // The connected player who has been in the room the longest, whatever their role
+        let (_, successor) = self
+            .members
+            .iter_mut()
+            .min_by_key(|(_, p)| (p.is_disconnected(), p.join_order))?;
+        successor.role = PlayerRole::Operator;
+        Some(NetworkPlayer::from(&*successor))
+    }
+
+    // Points the player to the client that reconnected as this player
+    pub fn rebind_player(&mut self, player: PlayerIdentifier, client: &GameClient) {
+        if let Some(data) = self.members.get_mut(player) {
+            data.client = client.handle();
+        }
+    }
+
+    // Returns: the updated player, if their presence changed
+    pub fn set_disconnected(
+        &mut self,
+        player: PlayerIdentifier,
+        disconnected: bool,
+    ) -> Option<NetworkPlayer> {
+        let data = self.members.get_mut(player)?;
+        if data.is_disconnected() == disconnected {
+            return None;
+        }
+        data.disconnected_at = disconnected.then(Instant::now);
+        Some(NetworkPlayer::from(&*data))
+    }
+
+    // Clears the room from every member's client, used when the room is closed
+    pub fn detach_members(&self) {
+        for (_, player) in self.members.iter() {
+            player.client.detach();
+        }
+    }
+
+    // Removes a member on behalf of a moderator, who must outrank them
+    pub fn kick_player(
+        &mut self,
+        actor: PlayerIdentifier,
+        account_id: &str,
+        ban: bool,
+    ) -> Result<PlayerData, PermissionError> {
+        self.check_role(actor, PlayerRole::Moderator)?;
+        let actor_role = self.members[actor].role;
+        let (target, target_data) = self
+            .members
+            .iter()
+            .find(|(_, p)| p.identity.account_id == account_id)
+            .ok_or(PermissionError::PlayerNotFound)?;
+        if target_data.role >= actor_role {
+            return Err(PermissionError::TargetOutranks);
+        }
+
+        if ban {
+            self.banned.insert(account_id.to_owned());
+        }
+        Ok(self.members.remove(target).expect("target is a member"))
+    }
+
+    // Returns: the message to broadcast, on the team channel if it is a team message
+    pub fn post_chat(
+        &mut self,
+        player: PlayerIdentifier,
+        content: &str,
+        team_only: bool,
+    ) -> Result<ChatMessage, ChatError> {
+        if !self.config.chat_enabled {
+            return Err(ChatError::Disabled);
+        }
+        let data = self.members.get_mut(player).ok_or(ChatError::NotInRoom)?;
+        let team = match data.team {
+            _ if !team_only => None,
+            Some(team) if self.config.play_mode == PlayMode::Teams => Some(team),
+            _ => return Err(ChatError::NoTeam),
+        };
+        let message = ChatMessage::new(NetworkPlayer::from(&*data), content, team)?;
+        data.chat_limiter.try_send()?;
+
+        if self.chat.len() >= CHAT_HISTORY_LENGTH {
+            self.chat.pop_front();
+        }
+        self.chat.push_back(message.clone());
+        self.touch();
+        Ok(message)
+    }
+
+    pub fn chat_history(&self, team: Option<TeamIdentifier>) -> Vec<ChatMessage> {
+        self.chat
+            .iter()
+            .filter(|message| message.is_visible_to(team))
+            .cloned()
+            .collect()
+    }
+
+    fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    // Whether nothing happened in the room for longer than `ROOM_IDLE_TIMEOUT`.
+    // Started games are never idle, a paused or long game can go without claims for a while.
+    pub fn is_idle(&self) -> bool {
+        !self.has_started() && self.last_activity.elapsed() >= ROOM_IDLE_TIMEOUT
+    }
+
+    // Whether every member failed to reconnect within the grace period
+    pub fn is_abandoned(&self) -> bool {
+        let grace_period = self.config.reconnect_grace_period();
+        self.members().all(|p| p.has_abandoned(grace_period))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.len() == 0
+    }
+
+    pub fn check_role(
+        &self,
+        player: PlayerIdentifier,
+        required: PlayerRole,
+    ) -> Result<(), PermissionError> {
+        match self.get_player(player) {
+            Some(p) if p.role >= required => Ok(()),
+            _ if required == PlayerRole::Operator => Err(PermissionError::NotOperator),
+            _ => Err(PermissionError::NotModerator),
+        }
     }
 
-    // Returns: whether the room should be closed
-    pub fn player_remove(&mut self, player: PlayerIdentifier) -> bool {
-        self.members.remove(player).map_or(false, |p| p.operator)
+    pub fn set_role(&mut self, account_id: &str, role: PlayerRole) -> Result<(), PermissionError> {
+        let player = self
+            .members
+            .iter_mut()
+            .map(|(_, p)| p)
+            .find(|p| p.identity.account_id == account_id)
+            .ok_or(PermissionError::PlayerNotFound)?;
+        // The operator role is only handed over when the operator leaves the room
+        if player.role == PlayerRole::Operator || role == PlayerRole::Operator {
+            return Err(PermissionError::ProtectedRole);
+        }
+        player.role = role;
+        Ok(())
     }
 
-    pub fn change_team(&mut self, player: PlayerIdentifier, team: TeamIdentifier) {
+    // Teams are fixed once the game starts, and are dealt by the server in randomized rooms
+    pub fn change_team(
+        &mut self,
+        player: PlayerIdentifier,
+        team: TeamIdentifier,
+    ) -> Result<(), RoomStateError> {
+        if self.has_started() {
+            return Err(RoomStateError::InvalidState(self.state));
+        }
+        if self.config.randomize {
+            return Err(TeamError::Randomized.into());
+        }
         if !self.team_exsits(team) {
-            return;
+            return Err(TeamError::NotFound.into());
         }
+        self.touch();
         if let Some(data) = self.members.get_mut(player) {
             data.team = Some(team);
         }
+        Ok(())
     }
 
-    pub fn set_config(&mut self, config: RoomConfiguration) {
-        self.config = config
+    // Returns: whether players without a team were placed in one, after a switch to team play
+    pub fn set_config(&mut self, config: RoomConfiguration) -> bool {
+        self.touch();
+        self.config = config;
+        self.assign_missing_teams()
     }
 
-    pub fn set_started(&mut self, started: bool) {
-        if started {
-            self.active = Some(ActiveGameData::new(self.maps.len()));
-        } else {
-            self.active = None;
+    // Players who joined a free-for-all or randomized room have no team until the game starts
+    fn assign_missing_teams(&mut self) -> bool {
+        if self.config.randomize || self.config.play_mode != PlayMode::Teams {
+            return false;
+        }
+        let unassigned: Vec<PlayerIdentifier> = self
+            .members
+            .iter()
+            .filter(|(_, p)| p.team.is_none())
+            .map(|(id, _)| id)
+            .collect();
+        for player in &unassigned {
+            self.members[*player].team = self.smallest_team();
+        }
+        !unassigned.is_empty()
+    }
+
+    // Transitions are validated by the server, see `RoomState::can_transition_to`
+    pub fn set_state(&mut self, state: RoomState) {
+        self.touch();
+        self.state = state;
+    }
+
+    // Timers of the previous game stop once a new round begins
+    pub fn next_round(&mut self) {
+        self.round += 1;
+    }
+
+    pub fn start_game(&mut self) {
+        self.active = Some(ActiveGameData::new(
+            self.config.grid_shape(),
+            self.config.play_mode,
+        ));
+    }
+
+    // Clears the previous game's grid, keeping players and teams for the next round
+    pub fn reset_game(&mut self) {
+        self.active = None;
+    }
+
+    pub fn record_win(&mut self, winner: &Owner) {
+        let &Owner::Team(team) = winner else {
+            return;
+        };
+        if let Some(team) = self.teams.iter_mut().find(|t| t.id == team) {
+            team.wins += 1;
         }
     }
 
-    pub fn get_cell_record(&mut self, cell_id: usize) -> Option<&mut MapCell> {
+    pub fn get_cell(&self, cell_id: usize) -> Option<&MapCell> {
+        self.active
+            .as_ref()
+            .and_then(|state| state.get_cell(cell_id))
+    }
+
+    pub fn is_steal(&self, cell_id: usize, claim: &MapClaim) -> bool {
+        self.active
+            .as_ref()
+            .is_some_and(|state| state.is_steal(cell_id, claim))
+    }
+
+    // Returns: the lines that were completed by this claim
+    pub fn claim_cell(&mut self, cell_id: usize, claim: MapClaim) -> Vec<BingoLine> {
+        self.touch();
         self.active
             .as_mut()
-            .and_then(|state| state.cells.get_mut(cell_id))
+            .map_or(Vec::new(), |state| state.claim_cell(cell_id, claim))
     }
 
     pub fn get_map(&self, uid: String) -> Option<(usize, &GameMap)> {
@@ -222,10 +645,39 @@ impl GameRoom {
             .next()
     }
 
-    pub fn check_for_bingos(&self) -> Vec<BingoLine> {
-        self.active.as_ref().map_or(Vec::new(), |a| {
-            a.check_for_bingos(self.config.grid_size.into())
-        })
+    pub fn check_win(&self) -> Option<Victory> {
+        self.active
+            .as_ref()
+            .and_then(|a| a.check_win(self.config.win_condition, self.config.line_count))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize_repr, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RoomState {
+    Lobby,
+    LoadingMaps,
+    Countdown,
+    Running,
+    Paused,
+    Ended,
+}
+
+impl RoomState {
+    pub fn can_transition_to(self, next: RoomState) -> bool {
+        use RoomState::*;
+        matches!(
+            (self, next),
+            (Lobby, LoadingMaps)
+                | (Lobby, Countdown)
+                | (LoadingMaps, Lobby)
+                | (Countdown, Running)
+                | (Running, Paused)
+                | (Running, Ended)
+                | (Paused, Running)
+                | (Paused, Ended)
+                | (Ended, LoadingMaps)
+        )
     }
 }
 
@@ -235,6 +687,69 @@ pub struct RoomStatus {
     pub teams: Vec<GameTeam>,
 }
 
+#[derive(Serialize_repr, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum CloseReason {
+    Empty,
+    ClosedByOperator,
+    // Every remaining member disconnected and did not come back in time
+    Abandoned,
+    Idle,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(
+        "The number of grid rows and columns must be between {} and {}.",
+        MIN_GRID_SIZE,
+        MAX_GRID_SIZE
+    )]
+    InvalidGridSize,
+    #[error(
+        "The team limit must be between {} and {}.",
+        MIN_TEAM_LIMIT,
+        MAX_TEAM_LIMIT
+    )]
+    InvalidTeamLimit,
+    #[error("The room already has more teams than the new team limit.")]
+    TooManyTeams,
+    #[error(
+        "The reconnection grace period cannot be longer than {} seconds.",
+        MAX_RECONNECT_GRACE_PERIOD.as_secs()
+    )]
+    InvalidGracePeriod,
+}
+
+#[derive(Error, Debug)]
+pub enum RoomStateError {
+    #[error("This action is not allowed while the room is in the {0:?} state.")]
+    InvalidState(RoomState),
+    #[error("The room no longer exists.")]
+    RoomNotFound,
+    #[error(transparent)]
+    Permission(#[from] PermissionError),
+    #[error(transparent)]
+    Team(#[from] TeamError),
+    #[error(transparent)]
+    Chat(#[from] ChatError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+#[derive(Error, Serialize, Debug, Clone, Copy)]
+pub enum PermissionError {
+    #[error("Only the room operator or a moderator can do this.")]
+    NotModerator,
+    #[error("Only the room operator can do this.")]
+    NotOperator,
+    #[error("This player is not in the room.")]
+    PlayerNotFound,
+    #[error("The room operator's role cannot be changed.")]
+    ProtectedRole,
+    #[error("You cannot remove a player whose role is equal to or above yours.")]
+    TargetOutranks,
+}
+
 #[derive(Error, Debug)]
 pub enum JoinRoomError {
     #[error("The room is already full.")]
@@ -243,26 +758,59 @@ pub enum JoinRoomError {
     DoesNotExist(String),
     #[error("The game has already started.")]
     HasStarted,
+    #[error("You have been banned from this room.")]
+    Banned,
 }
 
 pub struct PlayerData {
     pub identity: PlayerIdentity,
     pub team: Option<TeamIdentifier>,
-    pub operator: bool,
-    pub disconnected: bool,
+    pub role: PlayerRole,
+    pub disconnected_at: Option<Instant>,
+    pub join_order: u32,
+    pub client: ClientHandle,
+    pub chat_limiter: RateLimiter,
+}
+
+impl PlayerData {
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected_at.is_some()
+    }
+
+    // Whether the player failed to reconnect within the grace period
+    pub fn has_abandoned(&self, grace_period: Duration) -> bool {
+        matches!(self.disconnected_at, Some(t) if t.elapsed() >= grace_period)
+    }
+}
+
+// Moderators (co-hosts) may manage the room and the game, only the operator may change roles
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PlayerRole {
+    Member,
+    Moderator,
+    Operator,
 }
 
 #[derive(Serialize, Clone)]
 pub struct NetworkPlayer {
+    // Display names are not unique, clients need this to tell players apart in results
+    // and to target them in role, kick and ban requests
+    pub account_id: String,
     pub name: String,
     pub team: Option<TeamIdentifier>,
+    pub role: PlayerRole,
+    pub disconnected: bool,
 }
 
 impl From<&PlayerData> for NetworkPlayer {
     fn from(value: &PlayerData) -> Self {
         Self {
+            account_id: value.identity.account_id.clone(),
             name: value.identity.display_name.clone(),
             team: value.team,
+            role: value.role,
+            disconnected: value.is_disconnected(),
         }
     }
 }
@@ -272,12 +820,118 @@ pub struct RoomConfiguration {
     pub size: u32,
     pub randomize: bool,
     pub chat_enabled: bool,
-    pub grid_size: u8,
+    #[serde(flatten)]
+    pub grid: GridDimensions,
     pub selection: MapMode,
     pub medal: Medal,
+    // Game duration in minutes, 0 means there is no limit
     pub time_limit: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mappack_id: Option<u32>,
+    #[serde(default)]
+    pub win_condition: WinCondition,
+    // Number of lines to complete in the Lines win condition
+    #[serde(default = "default_line_count")]
+    pub line_count: u32,
+    #[serde(default)]
+    pub claim_policy: ClaimPolicy,
+    #[serde(default)]
+    pub play_mode: PlayMode,
+    #[serde(default = "default_max_teams")]
+    pub max_teams: usize,
+    // Seconds that players who dropped out of a started game have to reconnect
+    #[serde(default = "default_reconnect_grace_period")]
+    pub reconnect_grace_period: u32,
+}
+
+fn default_line_count() -> u32 {
+    1
+}
+
+fn default_max_teams() -> usize {
+    DEFAULT_TEAM_LIMIT
+}
+
+fn default_reconnect_grace_period() -> u32 {
+    DEFAULT_RECONNECT_GRACE_PERIOD.as_secs() as u32
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "GridDimensionsData", into = "GridDimensionsData")]
+pub struct GridDimensions {
+    pub rows: u8,
+    pub columns: u8,
+}
+
+// Older clients only know grid_size, which stands for a square grid.
+// It is sent along with the rows and columns whenever the grid is square.
+#[derive(Serialize, Deserialize)]
+struct GridDimensionsData {
+    grid_rows: Option<u8>,
+    grid_columns: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grid_size: Option<u8>,
+}
+
+impl From<GridDimensions> for GridDimensionsData {
+    fn from(value: GridDimensions) -> Self {
+        Self {
+            grid_rows: Some(value.rows),
+            grid_columns: Some(value.columns),
+            grid_size: (value.rows == value.columns).then_some(value.rows),
+        }
+    }
+}
+
+impl TryFrom<GridDimensionsData> for GridDimensions {
+    type Error = &'static str;
+
+    fn try_from(value: GridDimensionsData) -> Result<Self, Self::Error> {
+        match (value.grid_rows, value.grid_columns, value.grid_size) {
+            (Some(rows), Some(columns), _) => Ok(Self { rows, columns }),
+            (None, None, Some(size)) => Ok(Self {
+                rows: size,
+                columns: size,
+            }),
+            _ => Err("expected grid_rows and grid_columns, or grid_size"),
+        }
+    }
+}
+
+impl RoomConfiguration {
+    pub fn grid_shape(&self) -> GridShape {
+        GridShape::new(self.grid.rows as usize, self.grid.columns as usize)
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.grid_shape().cell_count()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let sizes = MIN_GRID_SIZE..=MAX_GRID_SIZE;
+        if !sizes.contains(&self.grid.rows) || !sizes.contains(&self.grid.columns) {
+            return Err(ConfigError::InvalidGridSize);
+        }
+        if !(MIN_TEAM_LIMIT..=MAX_TEAM_LIMIT).contains(&self.max_teams) {
+            return Err(ConfigError::InvalidTeamLimit);
+        }
+        if self.reconnect_grace_period() > MAX_RECONNECT_GRACE_PERIOD {
+            return Err(ConfigError::InvalidGracePeriod);
+        }
+        Ok(())
+    }
+
+    pub fn reconnect_grace_period(&self) -> Duration {
+        Duration::from_secs(self.reconnect_grace_period as u64)
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        if self.time_limit == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.time_limit as u64 * 60))
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
@@ -288,6 +942,40 @@ pub enum MapMode {
     Mappack,
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WinCondition {
+    // First team to complete `line_count` distinct lines
+    #[default]
+    Lines,
+    // First team to own every cell of the grid
+    Blackout,
+    // Team with the most cells when the grid is filled or time runs out
+    MostCells,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PlayMode {
+    #[default]
+    Teams,
+    // Every player plays for themselves, teams are ignored
+    FreeForAll,
+}
+
+// How a claimed cell can be taken over by another claim
+#[derive(Clone, Copy, Debug, Default, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ClaimPolicy {
+    // A faster time steals the cell
+    #[default]
+    StealByTime,
+    // The first claim owns the cell for the rest of the game
+    Lockout,
+    // Only a strictly better medal steals the cell
+    StealByMedal,
+}
+
 #[derive(Clone, Copy, Debug, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
 #[repr(u32)]
 pub enum Medal {
@@ -297,3 +985,256 @@ pub enum Medal {
     Bronze,
     None,
 }
+
+impl Medal {
+    // Medals are ordered from best to worst
+    pub fn is_at_least(self, required: Medal) -> bool {
+        self as u32 <= required as u32
+    }
+
+    pub fn is_better_than(self, other: Medal) -> bool {
+        (self as u32) < other as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn identity(name: &str) -> PlayerIdentity {
+        PlayerIdentity {
+            account_id: format!("account-{}", name),
+            display_name: name.to_owned(),
+        }
+    }
+
+    fn test_room() -> GameRoom {
+        let config = config_with(r#""grid_rows": 3, "grid_columns": 3,"#).unwrap();
+        let mut room = GameRoom::create(
+            "Room".to_owned(),
+            "ABC".to_owned(),
+            config,
+            ChannelAddress::from_raw_parts(0, 0),
+        );
+        for i in 1..=2 {
+            room.create_team(ChannelAddress::from_raw_parts(i, 0), None, None)
+                .unwrap();
+        }
+        room
+    }
+
+    async fn join(room: &mut GameRoom, name: &str, role: PlayerRole) -> PlayerIdentifier {
+        let id = room.join_count;
+        room.add_player(identity(name), ClientHandle::for_test(id).await, role)
+    }
+
+    fn config_with(grid: &str) -> Result<RoomConfiguration, serde_json::Error> {
+        serde_json::from_str(&format!(
+            r#"{{
+                "size": 8,
+                "randomize": false,
+                "chat_enabled": true,
+                {}
+                "selection": 0,
+                "medal": 0,
+                "time_limit": 0
+            }}"#,
+            grid
+        ))
+    }
+
+    #[test]
+    fn check_grid_dimensions() {
+        let config = config_with(r#""grid_rows": 3, "grid_columns": 7,"#).unwrap();
+        assert_eq!(
+            config.grid,
+            GridDimensions {
+                rows: 3,
+                columns: 7
+            }
+        );
+
+        let legacy = config_with(r#""grid_size": 5,"#).unwrap();
+        assert_eq!(
+            legacy.grid,
+            GridDimensions {
+                rows: 5,
+                columns: 5
+            }
+        );
+
+        // Rows and columns are always sent, grid_size only for square grids
+        let json = serde_json::to_value(&legacy).unwrap();
+        assert_eq!(json["grid_rows"], 5);
+        assert_eq!(json["grid_columns"], 5);
+        assert_eq!(json["grid_size"], 5);
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["grid_rows"], 3);
+        assert!(json.get("grid_size").is_none());
+
+        assert!(config_with(r#""grid_rows": 3,"#).is_err());
+        assert!(config_with("").is_err());
+    }
+
+    #[test]
+    fn check_state_transitions() {
+        use RoomState::*;
+        // Rooms only open to players once their maps are loaded, and go back to loading on rematch
+        assert!(Lobby.can_transition_to(LoadingMaps));
+        assert!(LoadingMaps.can_transition_to(Lobby));
+        assert!(!LoadingMaps.can_transition_to(Countdown));
+        assert!(Lobby.can_transition_to(Countdown));
+        assert!(Countdown.can_transition_to(Running));
+        assert!(!Countdown.can_transition_to(Paused));
+        assert!(Running.can_transition_to(Paused) && Paused.can_transition_to(Running));
+        assert!(Running.can_transition_to(Ended) && Paused.can_transition_to(Ended));
+        assert!(Ended.can_transition_to(LoadingMaps));
+        assert!(!Ended.can_transition_to(Lobby));
+        assert!(!Ended.can_transition_to(Running));
+        assert!(!Running.can_transition_to(Running));
+    }
+
+    #[tokio::test]
+    async fn check_host_succession() {
+        let mut room = test_room();
+        let host = join(&mut room, "host", PlayerRole::Operator).await;
+        let dropped = join(&mut room, "dropped", PlayerRole::Member).await;
+        let oldest = join(&mut room, "oldest", PlayerRole::Member).await;
+        join(&mut room, "moderator", PlayerRole::Moderator).await;
+        room.set_disconnected(dropped, true);
+
+        // Disconnected players are skipped, roles don't matter
+        let new_host = room.player_remove(host).unwrap();
+        assert_eq!(new_host.account_id, "account-oldest");
+        assert_eq!(room.get_player(oldest).unwrap().role, PlayerRole::Operator);
+
+        // Removing anyone else keeps the current host
+        assert!(room.player_remove(dropped).is_none());
+    }
+
+    #[test]
+    fn check_team_limit() {
+        let mut room = test_room();
+        room.config.max_teams = MIN_TEAM_LIMIT;
+        assert!(room.config.validate().is_ok());
+        assert!(matches!(
+            room.create_team(ChannelAddress::from_raw_parts(3, 0), None, None),
+            Err(TeamError::LimitReached)
+        ));
+
+        room.config.max_teams = MAX_TEAM_LIMIT + 1;
+        assert!(room.config.validate().is_err());
+    }
+
+    #[test]
+    fn check_team_styles_at_limit() {
+        let mut room = test_room();
+        room.config.max_teams = MAX_TEAM_LIMIT;
+        for i in room.teams.len()..MAX_TEAM_LIMIT {
+            room.create_team(ChannelAddress::from_raw_parts(i + 1, 0), None, None)
+                .unwrap();
+        }
+        assert_eq!(room.teams.len(), MAX_TEAM_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn check_switch_to_teams() {
+        let mut room = test_room();
+        let mut config = room.config.clone();
+        config.play_mode = PlayMode::FreeForAll;
+        assert!(!room.set_config(config.clone()));
+        for name in ["a", "b", "c"] {
+            join(&mut room, name, PlayerRole::Member).await;
+        }
+        assert!(room.members().all(|p| p.team.is_none()));
+
+        config.play_mode = PlayMode::Teams;
+        assert!(room.set_config(config));
+        let mut sizes: Vec<usize> = room
+            .teams()
+            .iter()
+            .map(|t| room.members().filter(|p| p.team == Some(t.id)).count())
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![1, 2]);
+    }
+
+    #[test]
+    fn check_idle_room() {
+        let mut room = test_room();
+        room.last_activity = Instant::now() - ROOM_IDLE_TIMEOUT;
+        assert!(room.is_idle());
+
+        for state in [RoomState::Countdown, RoomState::Running, RoomState::Paused] {
+            room.state = state;
+            assert!(!room.is_idle());
+        }
+        room.state = RoomState::Ended;
+        assert!(room.is_idle());
+    }
+
+    #[tokio::test]
+    async fn check_delete_team() {
+        let mut room = test_room();
+        room.create_team(ChannelAddress::from_raw_parts(3, 0), None, None)
+            .unwrap();
+        let ids: Vec<TeamIdentifier> = room.teams.iter().map(|t| t.id).collect();
+        let player = join(&mut room, "player", PlayerRole::Member).await;
+        room.change_team(player, ids[1]).unwrap();
+
+        // Remaining teams keep their identifiers, players are moved to the chosen team
+        room.delete_team(ids[1], Some(ids[2])).unwrap();
+        let remaining: Vec<TeamIdentifier> = room.teams.iter().map(|t| t.id).collect();
+        assert_eq!(remaining, vec![ids[0], ids[2]]);
+        assert_eq!(room.get_player(player).unwrap().team, Some(ids[2]));
+
+        // Identifiers of deleted teams are not given out again
+        let id = room
+            .create_team(ChannelAddress::from_raw_parts(4, 0), None, None)
+            .unwrap()
+            .id;
+        assert!(!ids.contains(&id));
+    }
+
+    #[tokio::test]
+    async fn check_change_team() {
+        let mut room = test_room();
+        let player = join(&mut room, "player", PlayerRole::Member).await;
+        let team = room.teams[1].id;
+        room.change_team(player, team).unwrap();
+        assert_eq!(room.get_player(player).unwrap().team, Some(team));
+        assert!(room.change_team(player, 99).is_err());
+
+        room.state = RoomState::Running;
+        assert!(room.change_team(player, room.teams[0].id).is_err());
+
+        room.state = RoomState::Lobby;
+        room.config.randomize = true;
+        assert!(room.change_team(player, room.teams[0].id).is_err());
+        assert_eq!(room.get_player(player).unwrap().team, Some(team));
+    }
+
+    #[tokio::test]
+    async fn check_abandoned_room() {
+        let mut room = test_room();
+        let first = join(&mut room, "first", PlayerRole::Operator).await;
+        let second = join(&mut room, "second", PlayerRole::Member).await;
+        room.set_disconnected(first, true);
+        assert!(!room.is_abandoned());
+
+        // A player who dropped out recently still has time to come back
+        let expired = DEFAULT_RECONNECT_GRACE_PERIOD + Duration::from_secs(1);
+        room.members[first].disconnected_at = Some(Instant::now() - expired);
+        room.set_disconnected(second, true);
+        assert!(!room.is_abandoned());
+
+        room.members[second].disconnected_at = Some(Instant::now() - expired);
+        assert!(room.is_abandoned());
+
+        // The grace period is taken from the room configuration
+        let mut config = room.config.clone();
+        config.reconnect_grace_period = 300;
+        room.set_config(config);
+        assert!(!room.is_abandoned());
+    }
+}
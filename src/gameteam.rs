@@ -1,30 +1,33 @@
 use serde::Serialize;
+use thiserror::Error;
 
-use crate::{channel::ChannelAddress, config::TEAMS, util::color::RgbColor};
+use crate::{
+    channel::ChannelAddress,
+    config::MAX_TEAM_NAME_LENGTH,
+    util::color::{ParseColorError, RgbColor},
+};
 
 pub type TeamIdentifier = usize;
 
 #[derive(Clone, Serialize)]
 pub struct GameTeam {
     pub id: TeamIdentifier,
-    pub name: &'static str,
+    pub name: String,
     pub color: RgbColor,
-    #[serde(skip_serializing)]
-    pub gen_index: usize,
+    // Games won in this room, across rematches
+    pub wins: u32,
     #[serde(skip_serializing)]
     pub channel_id: ChannelAddress,
 }
 
 impl GameTeam {
-    pub fn new(id: usize, index: usize, channel_id: ChannelAddress) -> Self {
-        let (name, color_string) = TEAMS[index];
-        let color = RgbColor::from_hex(color_string).expect("team color parsing failed");
+    pub fn new(id: usize, name: String, color: RgbColor, channel_id: ChannelAddress) -> Self {
         Self {
             id: id,
             name,
             color,
+            wins: 0,
             channel_id,
-            gen_index: index,
         }
     }
 }
@@ -36,3 +39,54 @@ impl PartialEq for GameTeam {
 }
 
 impl Eq for GameTeam {}
+
+pub fn parse_team_name(name: &str) -> Result<String, TeamError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TEAM_NAME_LENGTH {
+        return Err(TeamError::InvalidName);
+    }
+    Ok(name.to_owned())
+}
+
+pub fn parse_team_color(color: &str) -> Result<RgbColor, TeamError> {
+    Ok(RgbColor::from_hex(color.trim_start_matches('#'))?)
+}
+
+#[derive(Error, Debug)]
+pub enum TeamError {
+    #[error("This team does not exist.")]
+    NotFound,
+    #[error("The room cannot have any more teams.")]
+    LimitReached,
+    #[error("The room must keep at least one team.")]
+    LastTeam,
+    #[error(
+        "Team names must be between 1 and {} characters long.",
+        MAX_TEAM_NAME_LENGTH
+    )]
+    InvalidName,
+    #[error("Invalid team color: {0}.")]
+    InvalidColor(#[from] ParseColorError),
+    #[error("No distinct team color is left, please choose one yourself.")]
+    NoStyleAvailable,
+    #[error("Another team is already named {0}.")]
+    DuplicateName(String),
+    #[error("This color is too similar to the color of team {0}.")]
+    SimilarColor(String),
+    #[error("Teams are assigned randomly in this room.")]
+    Randomized,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_team_style_parsing() {
+        assert_eq!(parse_team_name("  Clan  ").unwrap(), "Clan");
+        assert!(parse_team_name("   ").is_err());
+        assert!(parse_team_name(&"a".repeat(MAX_TEAM_NAME_LENGTH + 1)).is_err());
+        assert_eq!(parse_team_color("#00FF00").unwrap(), RgbColor(0, 255, 0));
+        assert!(parse_team_color("green").is_err());
+    }
+}
@@ -11,6 +11,7 @@ use tracing::info;
 use tracing_subscriber::FmtSubscriber;
 
 pub mod channel;
+pub mod chat;
 pub mod client;
 pub mod config;
 pub mod events;
@@ -22,6 +23,7 @@ pub mod protocol;
 pub mod requests;
 pub mod rest;
 pub mod server;
+pub mod session;
 pub mod sync;
 pub mod util;
 
@@ -46,6 +48,7 @@ async fn main() {
             .expect("authentification route to be valid"),
     );
     let auth_arc = Arc::new(authenticator);
+    let resume_tokens = Arc::new(session::ResumeTokens::new());
 
     let (maps_tx, maps_rx) = unbounded_channel();
     let server = server::GameServer::new(maps_tx);
@@ -73,9 +76,10 @@ async fn main() {
 
         info!("accepted a connection");
         let auth = auth_arc.clone();
+        let resume_tokens = resume_tokens.clone();
         let server = server_arc.clone();
         tokio::spawn(async move {
-            let mut protocol = protocol::Protocol::new(socket, auth);
+            let mut protocol = protocol::Protocol::new(socket, auth, resume_tokens);
             let state = match protocol.handshake(&server).await {
                 Some(s) => s,
                 None => return,
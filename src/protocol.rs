@@ -8,26 +8,34 @@ use tokio::net::TcpStream;
 use tracing::{error, info, warn};
 
 use crate::config;
-use crate::gameroom::PlayerRef;
 use crate::rest::auth::{Authenticator, PlayerIdentity, ValidationError};
 use crate::server::GameServer;
+use crate::session::{ResumeTokenError, ResumeTokens};
 use crate::util::version::Version;
 
 pub struct Protocol {
     socket: TcpStream,
     auth: Arc<Authenticator>,
+    resume_tokens: Arc<ResumeTokens>,
     state: Mutex<ConnectionState>,
 }
 
 impl Protocol {
-    pub fn new(socket: TcpStream, auth: Arc<Authenticator>) -> Self {
+    pub fn new(
+        socket: TcpStream,
+        auth: Arc<Authenticator>,
+        resume_tokens: Arc<ResumeTokens>,
+    ) -> Self {
         Self {
             socket: socket,
             auth,
+            resume_tokens,
             state: Mutex::new(ConnectionState::Closed),
         }
     }
 
+    // Authenticates the client, the handshake is completed by `handshake_success` once the
+    // client's session is open
     pub async fn handshake(&mut self, server: &GameServer) -> Option<InitialClientState> {
         if self.state() != ConnectionState::Closed {
             warn!(
@@ -67,27 +75,44 @@ impl Protocol {
             return None;
         }
 
-        // Authentification
-        let validation_result = self.auth.validate(req.token).await;
-        let identity = match validation_result {
-            Ok(i) => i,
-            Err(e) => {
-                error!("{}", e);
-                let code = match e {
-                    ValidationError::RequestError(_) => HandshakeCode::AuthFailure,
-                    ValidationError::BackendError(_) => HandshakeCode::AuthRefused,
-                };
-                self.handshake_end(code).await;
+        // Authentification, skipping Openplanet if the client has a valid resume token
+        let resumed = match req.resume_token {
+            Some(resume_token) => match self.resume_tokens.verify(&resume_token) {
+                Ok(claims) if server.is_current_session(&claims) => Some(claims),
+                Ok(_) => {
+                    info!("Rejected resume token: {}", ResumeTokenError::Revoked);
+                    None
+                }
+                Err(e) => {
+                    info!("Rejected resume token: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let resume_expiry = resumed.as_ref().map(|claims| claims.expires);
+        let identity = match (resumed, req.token) {
+            (Some(claims), _) => claims.identity(),
+            (None, Some(token)) => match self.auth.validate(token).await {
+                Ok(i) => i,
+                Err(e) => {
+                    error!("{}", e);
+                    let code = match e {
+                        ValidationError::RequestError(_) => HandshakeCode::AuthFailure,
+                        ValidationError::BackendError(_) => HandshakeCode::AuthRefused,
+                    };
+                    self.handshake_end(code).await;
+                    return None;
+                }
+            },
+            (None, None) => {
+                self.handshake_end(HandshakeCode::ResumeRefused).await;
                 return None;
             }
         };
 
         info!("Authentificated client: {:?}", identity);
-        let reconnect_state = server.try_reconnect(&identity);
-        self.handshake_success(&identity, reconnect_state.is_some())
-            .await;
-        self.set_state(ConnectionState::Connected);
-        return Some(InitialClientState::new(identity, reconnect_state));
+        return Some(InitialClientState::new(identity, resume_expiry));
     }
 
     fn state(&self) -> ConnectionState {
@@ -103,6 +128,7 @@ impl Protocol {
             &to_string(&HandshakeResponse {
                 code,
                 username: None,
+                resume_token: None,
             })
             .expect("json conversion to pass"),
         )
@@ -110,7 +136,13 @@ impl Protocol {
         .unwrap_or_default();
     }
 
-    async fn handshake_success(&self, identity: &PlayerIdentity, reconnect: bool) {
+    pub async fn handshake_success(
+        &self,
+        identity: &PlayerIdentity,
+        reconnect: bool,
+        generation: u64,
+        resume_expiry: Option<u64>,
+    ) {
         self.send_inner(
             &to_string(&HandshakeResponse {
                 code: if reconnect {
@@ -119,11 +151,16 @@ impl Protocol {
                     HandshakeCode::Ok
                 },
                 username: Some(identity.display_name.clone()),
+                resume_token: Some(
+                    self.resume_tokens
+                        .issue(identity, generation, resume_expiry),
+                ),
             })
             .expect("json conversion to pass"),
         )
         .await
         .unwrap_or_default();
+        self.set_state(ConnectionState::Connected);
     }
 
     pub async fn recv(&self) -> io::Result<String> {
@@ -162,6 +199,17 @@ impl Protocol {
         self.set_state(ConnectionState::Closing)
     }
 
+    pub fn is_closing(&self) -> bool {
+        self.state() == ConnectionState::Closing
+    }
+
+    // The client will see the end of the stream and disconnect
+    pub async fn shutdown(&self) {
+        self.close();
+        let mut writer = &self.socket;
+        writer.shutdown().await.unwrap_or_default();
+    }
+
     pub async fn error(&self, err: &str) {
         // TODO: stub
         error!(err);
@@ -170,19 +218,27 @@ impl Protocol {
 
 pub struct InitialClientState {
     pub identity: PlayerIdentity,
-    pub player: Option<PlayerRef>,
+    // Expiry of the resume token the client authentificated with, if any
+    pub resume_expiry: Option<u64>,
 }
 
 impl InitialClientState {
-    pub fn new(identity: PlayerIdentity, player: Option<PlayerRef>) -> Self {
-        Self { identity, player }
+    pub fn new(identity: PlayerIdentity, resume_expiry: Option<u64>) -> Self {
+        Self {
+            identity,
+            resume_expiry,
+        }
     }
 }
 
 #[derive(Deserialize)]
 struct HandshakeRequest {
     version: String,
-    token: String,
+    // Openplanet authentification token, may be omitted when resuming a session
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    resume_token: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -190,6 +246,8 @@ struct HandshakeResponse {
     code: HandshakeCode,
     #[serde(skip_serializing_if = "Option::is_none")]
     username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
 }
 
 #[derive(Serialize_repr)]
@@ -201,6 +259,7 @@ enum HandshakeCode {
     AuthFailure = 3,
     AuthRefused = 4,
     CanReconnect = 5,
+    ResumeRefused = 6,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    gameroom::{Medal, RoomConfiguration, RoomStatus},
+    gamedata::ClaimError,
+    gameroom::{Medal, PermissionError, PlayerRole, RoomConfiguration, RoomStateError, RoomStatus},
     gameteam::GameTeam,
     sync::SyncPacket,
 };
@@ -42,13 +43,45 @@ pub enum Request {
     EditRoomConfig {
         config: RoomConfiguration,
     },
-    CreateTeam,
+    CreateTeam {
+        name: Option<String>,
+        color: Option<String>,
+    },
+    DeleteTeam {
+        team_id: usize,
+        move_to: Option<usize>,
+    },
+    EditTeam {
+        team_id: usize,
+        name: Option<String>,
+        color: Option<String>,
+    },
     StartGame,
+    PauseGame,
+    ResumeGame,
+    Rematch,
+    ReloadMaps,
+    SetPlayerRole {
+        account_id: String,
+        role: PlayerRole,
+    },
+    KickPlayer {
+        account_id: String,
+    },
+    BanPlayer {
+        account_id: String,
+    },
+    CloseRoom,
     ClaimCell {
         uid: String,
         time: u64,
         medal: Medal,
     },
+    SendChat {
+        message: String,
+        #[serde(default)]
+        team_only: bool,
+    },
     Sync,
 }
 
@@ -60,13 +93,21 @@ pub enum Response {
     Error {
         error: String,
     },
+    ClaimRejected {
+        error: String,
+        reason: ClaimError,
+    },
+    PermissionDenied {
+        error: String,
+        reason: PermissionError,
+    },
     CreateRoom(CreateRoomResponse),
     JoinRoom {
         name: String,
         config: RoomConfiguration,
         status: RoomStatus,
     },
-    Sync(SyncPacket),
+    Sync(Box<SyncPacket>),
 }
 
 #[derive(Deserialize)]
@@ -82,3 +123,26 @@ pub struct CreateRoomResponse {
     pub max_teams: usize,
     pub teams: Vec<GameTeam>,
 }
+
+impl From<RoomStateError> for Response {
+    fn from(value: RoomStateError) -> Self {
+        match value {
+            RoomStateError::Permission(reason) => Self::PermissionDenied {
+                error: reason.to_string(),
+                reason,
+            },
+            e => Self::Error {
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<ClaimError> for Response {
+    fn from(value: ClaimError) -> Self {
+        Self::ClaimRejected {
+            error: value.to_string(),
+            reason: value,
+        }
+    }
+}
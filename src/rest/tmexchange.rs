@@ -100,20 +100,129 @@ struct TMExchangeMap {
     username: String,
     #[serde(rename = "AuthorTime")]
     author_time: i32,
+    #[serde(default)]
+    gold_target: Option<i32>,
+    #[serde(default)]
+    silver_target: Option<i32>,
+    #[serde(default)]
+    bronze_target: Option<i32>,
 }
 
 impl Into<GameMap> for TMExchangeMap {
     fn into(self) -> GameMap {
+        // Medal times that TMX does not report are derived from the author time like in the game
+        let author_time = reported_time(Some(self.author_time));
+        let medal_time = |target: Option<i32>, percent: u64| {
+            reported_time(target).or(author_time.map(|time| derived_time(time, percent)))
+        };
         GameMap {
             track_id: self.track_id,
             uid: self.track_uid,
             name: self.name,
             author_name: self.username,
+            author_time,
+            gold_time: medal_time(self.gold_target, 106),
+            silver_time: medal_time(self.silver_target, 120),
+            bronze_time: medal_time(self.bronze_target, 150),
         }
     }
 }
 
+// TMX reports missing medal times as 0 or leaves them out
+fn reported_time(time: Option<i32>) -> Option<u64> {
+    time.filter(|t| *t > 0).map(|t| t as u64)
+}
+
+// Scales the author time by a percentage, rounded up to the next second
+fn derived_time(author_time: u64, percent: u64) -> u64 {
+    let time = author_time * percent / 100;
+    (time / 1000 + 1) * 1000
+}
+
 #[derive(Deserialize)]
 struct MapsResult {
     results: [TMExchangeMap; 1],
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gameroom::Medal;
+
+    // A map search result as returned by /mapsearch2/search, which has no medal targets
+    const SEARCH_RESULT: &str = r#"{
+        "TrackID": 63781,
+        "UserID": 21604,
+        "Username": "Hylis",
+        "GbxMapName": "$s$fffWinter 2023 - 01",
+        "AuthorLogin": "d2372a08-a8a1-46cb-97fb-23a161d85ad0",
+        "MapType": "TM_Race",
+        "TitlePack": "Trackmania",
+        "TrackUID": "b9LH4FbvtDwXO2JlQwbxs1N9Gxc",
+        "Mood": "48x48Day",
+        "DisplayCost": 2514,
+        "ModName": "",
+        "Lightmap": 8,
+        "ExeVersion": "3.3.0",
+        "ExeBuild": "2022-12-09_11_31",
+        "AuthorTime": 22379,
+        "ParserVersion": 2,
+        "UploadedAt": "2023-01-01T17:00:12.567",
+        "UpdatedAt": "2023-01-01T17:00:12.567",
+        "Name": "Winter 2023 - 01",
+        "Tags": "3",
+        "TypeName": "Race",
+        "StyleName": "Race",
+        "EnvironmentName": "Stadium",
+        "VehicleName": "CarSport",
+        "UnlimiterRequired": false,
+        "RouteName": "Single",
+        "LengthName": "15 secs",
+        "DifficultyName": "Beginner",
+        "Laps": 1,
+        "ReplayWRID": null,
+        "ReplayCount": 0,
+        "TrackValue": 0,
+        "Comments": "",
+        "Unlisted": false,
+        "AwardCount": 12,
+        "CommentCount": 0,
+        "ReplayType": 0,
+        "HasScreenshot": false,
+        "HasThumbnail": true,
+        "HasGhostBlocks": true,
+        "EmbeddedObjectsCount": 0
+    }"#;
+
+    #[test]
+    fn check_medal_times() {
+        // Medal times missing from TMX results are derived from the author time
+        let map: GameMap = serde_json::from_str::<TMExchangeMap>(SEARCH_RESULT)
+            .unwrap()
+            .into();
+        assert_eq!(map.medal_time(Medal::Author), Some(22379));
+        assert_eq!(map.medal_time(Medal::Gold), Some(24000));
+        assert_eq!(map.medal_time(Medal::Silver), Some(27000));
+        assert_eq!(map.medal_time(Medal::Bronze), Some(34000));
+
+        // Reported targets take precedence, those set to 0 are derived too
+        let mut result: serde_json::Value = serde_json::from_str(SEARCH_RESULT).unwrap();
+        result["GoldTarget"] = 23000.into();
+        result["SilverTarget"] = 0.into();
+        let map: GameMap = serde_json::from_value::<TMExchangeMap>(result.clone())
+            .unwrap()
+            .into();
+        assert_eq!(map.medal_time(Medal::Gold), Some(23000));
+        assert_eq!(map.medal_time(Medal::Silver), Some(27000));
+
+        // Without an author time, nothing is known about the medals
+        result["AuthorTime"] = 0.into();
+        result["GoldTarget"] = 0.into();
+        let map: GameMap = serde_json::from_value::<TMExchangeMap>(result)
+            .unwrap()
+            .into();
+        assert_eq!(map.medal_time(Medal::Author), None);
+        assert_eq!(map.medal_time(Medal::Gold), None);
+        assert_eq!(map.medal_time(Medal::None), None);
+    }
+}
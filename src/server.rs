@@ -1,23 +1,30 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use generational_arena::Arena;
 use rand::{distributions::Uniform, prelude::Distribution};
-use tokio::{join, task};
-use tracing::error;
+use tokio::{
+    join, task,
+    time::{interval, sleep},
+};
+use tracing::{error, info, warn};
 
 use crate::{
     channel::ChannelCollection,
     client::{ClientId, GameClient},
     config::{self, JOINCODE_CHARS, JOINCODE_LENGTH},
     events::ServerEvent,
-    gamedata::MapClaim,
-    gamemap::{MapQuery, MapStock, Receiver, Sender},
+    gamedata::{ClaimError, MapClaim, Victory},
+    gamemap::{GameMap, MapQuery, MapStock, Receiver, Sender},
     gameroom::{
-        GameRoom, JoinRoomError, MapMode, Medal, NetworkPlayer, PlayerRef, RoomConfiguration,
-        RoomIdentifier, RoomStatus,
+        ClaimPolicy, CloseReason, ConfigError, GameRoom, JoinRoomError, MapMode, Medal,
+        NetworkPlayer, PlayMode, PlayerIdentifier, PlayerRef, PlayerRole, RoomConfiguration,
+        RoomIdentifier, RoomState, RoomStateError, RoomStatus,
     },
     gameteam::{GameTeam, TeamIdentifier},
-    rest::auth::PlayerIdentity,
+    session::{DisplacedReason, ResumeClaims, SessionRegistry},
     sync::{build_sync_packet, SyncPacket},
 };
 
@@ -25,6 +32,7 @@ pub struct GameServer {
     rooms: Mutex<Arena<GameRoom>>,
     channels: ChannelCollection,
     maps: MapStock,
+    sessions: SessionRegistry,
 }
 
 impl GameServer {
@@ -34,11 +42,34 @@ impl GameServer {
             rooms: Mutex::new(Arena::new()),
             channels: ChannelCollection::new(),
             maps: map_stock,
+            sessions: SessionRegistry::new(),
         }
     }
 
     pub async fn spawn(self: Arc<Self>, maps_rx: Receiver) {
-        join! { self.maps.fetch_loop(maps_rx) };
+        join! { self.maps.fetch_loop(maps_rx), self.clone().sweep_loop() };
+    }
+
+    async fn sweep_loop(self: Arc<Self>) {
+        let mut interval = interval(config::ROOM_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.close_idle_rooms();
+            self.sessions.prune();
+        }
+    }
+
+    fn close_idle_rooms(&self) {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let idle: Vec<RoomIdentifier> = lock
+            .iter()
+            .filter(|(_, room)| room.is_idle())
+            .map(|(id, _)| id)
+            .collect();
+        for room_id in idle {
+            info!("closing idle room {}", lock[room_id].join_code());
+            self.remove_room(&mut lock, room_id, CloseReason::Idle);
+        }
     }
 
     pub fn create_new_room(
@@ -72,30 +103,28 @@ impl GameServer {
             self.channels.create_one(),
         );
         let room_name = room.name().to_owned();
+        room.set_state(RoomState::LoadingMaps);
 
         // Add the two starting teams and the host
         let team1 = room
-            .create_team(self.channels.create_one())
+            .create_team(self.channels.create_one(), None, None)
             .expect("creating initial 1st team")
             .clone();
         let team2 = room
-            .create_team(self.channels.create_one())
+            .create_team(self.channels.create_one(), None, None)
             .expect("creating initial 2nd team")
             .clone();
         let player_id = room
-            .player_join(&host, true)
+            .player_join(&host, PlayerRole::Operator)
             .expect("adding host to a new room");
         self.channels.subscribe(room.channel(), host);
+        self.update_team_channels(&room);
 
         let code = room.join_code().to_owned();
         let room_id = self.rooms.lock().expect("lock poisoned").insert(room);
         tokio::spawn(self.clone().load_maps(
             room_id,
-            MapQuery::new(
-                config.selection,
-                config.grid_size as usize * config.grid_size as usize,
-                config.mappack_id,
-            ),
+            MapQuery::new(config.selection, config.cell_count(), config.mappack_id),
         ));
         ((room_id, player_id), room_name, code, vec![team1, team2])
     }
@@ -108,14 +137,19 @@ impl GameServer {
             match maps_result {
                 Ok(maps) => {
                     // Check if map mode has changed during the load. If so, return the maps to the queue
-                    if room.config().selection != query.mode {
+                    if room.config().selection != query.mode
+                        || room.config().mappack_id != query.mappack_id
+                    {
                         self.maps.extend_maps(query.mode, maps);
                         return;
                     }
 
                     room.add_maps(maps);
+                    // The grid may have been made smaller during the load
+                    self.return_extra_maps(room);
                     self.channels
-                        .broadcast(room.channel(), ServerEvent::MapsLoadResult { error: None })
+                        .broadcast(room.channel(), ServerEvent::MapsLoadResult { error: None });
+                    self.update_loading_state(room);
                 }
                 Err(e) => {
                     error!("load_maps error: {}", e);
@@ -124,67 +158,231 @@ impl GameServer {
                         ServerEvent::MapsLoadResult {
                             error: Some(e.to_string()),
                         },
-                    )
+                    );
+                    // The room only returns to the lobby if it still has enough maps for a grid,
+                    // otherwise moderators can retry the load with `reload_maps`
+                    self.update_loading_state(room);
                 }
             }
         }
     }
 
-    pub fn edit_room_config(self: &Arc<Self>, room_id: RoomIdentifier, config: RoomConfiguration) {
-        // TODO: handle errors
-        if let Some(room) = self.rooms.lock().expect("lock poisoned").get_mut(room_id) {
-            let old_grid_size = room.config().grid_size;
-            let old_selection = room.config().selection;
-            let old_mappack = room.config().mappack_id;
-            room.set_config(config.clone());
+    // Maps beyond the grid's cell count are given back to the stock
+    fn return_extra_maps(&self, room: &mut GameRoom) {
+        let extra = room.maps().len().saturating_sub(room.config().cell_count());
+        if extra > 0 {
+            self.maps
+                .extend_maps(room.config().selection, room.remove_maps(extra));
+        }
+    }
+
+    // Moves the room between the lobby and map loading states depending on its map count
+    fn update_loading_state(&self, room: &mut GameRoom) {
+        let state = if room.maps().len() == room.config().cell_count() {
+            RoomState::Lobby
+        } else {
+            RoomState::LoadingMaps
+        };
+        if room.state() != state {
+            self.transition(room, state).ok();
+        }
+    }
+
+    fn transition(&self, room: &mut GameRoom, state: RoomState) -> Result<(), RoomStateError> {
+        if !room.state().can_transition_to(state) {
+            return Err(RoomStateError::InvalidState(room.state()));
+        }
+        room.set_state(state);
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomStateChange { state });
+        Ok(())
+    }
+
+    // Loads a new set of maps before returning the previous ones,
+    // so that they can't be drawn again for the same room
+    async fn replace_maps(
+        self: Arc<Self>,
+        room: RoomIdentifier,
+        query: MapQuery,
+        old_maps: Vec<GameMap>,
+    ) {
+        let mode = query.mode;
+        self.clone().load_maps(room, query).await;
+        self.maps.extend_maps(mode, old_maps);
+    }
+
+    pub fn edit_room_config(
+        self: &Arc<Self>,
+        (room_id, player_id): PlayerRef,
+        config: RoomConfiguration,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        if !matches!(room.state(), RoomState::Lobby | RoomState::LoadingMaps) {
+            return Err(RoomStateError::InvalidState(room.state()));
+        }
+        if room.teams().len() > config.max_teams {
+            return Err(ConfigError::TooManyTeams.into());
+        }
 
-            // Fetch / Remove maps if there was a config change in map mode.
-            if config.selection != old_selection
-                || (config.mappack_id.is_some() && config.mappack_id != old_mappack)
-            {
-                self.maps.extend_maps(old_selection, room.remove_all_maps());
+        let old_cell_count = room.config().cell_count();
+        let old_selection = room.config().selection;
+        let old_mappack = room.config().mappack_id;
+        if room.set_config(config.clone()) {
+            self.update_team_channels(room);
+            self.channels
+                .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        }
+
+        // Fetch / Remove maps if there was a config change in map mode.
+        if config.selection != old_selection
+            || (config.mappack_id.is_some() && config.mappack_id != old_mappack)
+        {
+            self.maps.extend_maps(old_selection, room.remove_all_maps());
+            tokio::spawn(self.clone().load_maps(
+                room_id,
+                MapQuery::new(config.selection, config.cell_count(), config.mappack_id),
+            ));
+        } else {
+            let map_diff = usize::abs_diff(config.cell_count(), old_cell_count);
+            if config.cell_count() > old_cell_count {
                 tokio::spawn(self.clone().load_maps(
                     room_id,
-                    MapQuery::new(
-                        config.selection,
-                        (config.grid_size * config.grid_size) as usize,
-                        config.mappack_id,
-                    ),
+                    MapQuery::new(config.selection, map_diff, config.mappack_id),
                 ));
-            } else {
-                let map_diff = usize::abs_diff(
-                    config.grid_size as usize * config.grid_size as usize,
-                    old_grid_size as usize * old_grid_size as usize,
-                );
-                if config.grid_size > old_grid_size {
-                    tokio::spawn(self.clone().load_maps(
-                        room_id,
-                        MapQuery::new(config.selection, map_diff, config.mappack_id),
-                    ));
-                } else if config.grid_size < old_grid_size {
-                    self.maps
-                        .extend_maps(old_selection, room.remove_maps(map_diff));
-                }
+            } else if config.cell_count() < old_cell_count {
+                self.return_extra_maps(room);
             }
+        }
 
-            self.channels
-                .broadcast(room.channel(), ServerEvent::RoomConfigUpdate(config));
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomConfigUpdate(config));
+        self.update_loading_state(room);
+        Ok(())
+    }
+
+    // Requests the maps that are still missing from the grid, after a load has failed
+    pub fn reload_maps(
+        self: &Arc<Self>,
+        (room_id, player_id): PlayerRef,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        if room.state() != RoomState::LoadingMaps {
+            return Err(RoomStateError::InvalidState(room.state()));
+        }
+
+        let config = room.config();
+        tokio::spawn(self.clone().load_maps(
+            room_id,
+            MapQuery::new(
+                config.selection,
+                config.cell_count() - room.maps().len(),
+                config.mappack_id,
+            ),
+        ));
+        Ok(())
+    }
+
+    pub fn add_team(
+        &self,
+        (room_id, player_id): PlayerRef,
+        name: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        let channel = self.channels.create_one();
+        if let Err(e) = room.create_team(channel, name, color) {
+            self.channels.remove(channel);
+            return Err(e.into());
         }
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
     }
 
-    pub fn add_team(&self, room: RoomIdentifier) {
-        if let Some(room) = self.rooms.lock().expect("lock poisoned").get_mut(room) {
-            room.create_team(self.channels.create_one());
-            self.channels
-                .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+    pub fn delete_team(
+        &self,
+        (room_id, player_id): PlayerRef,
+        team: TeamIdentifier,
+        move_to: Option<TeamIdentifier>,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        if !matches!(room.state(), RoomState::Lobby | RoomState::LoadingMaps) {
+            return Err(RoomStateError::InvalidState(room.state()));
         }
+
+        let removed = room.delete_team(team, move_to)?;
+        self.channels.remove(removed.channel_id);
+        self.update_team_channels(room);
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
     }
 
-    pub fn change_team(&self, (room, player): PlayerRef, team: TeamIdentifier) {
-        if let Some(room) = self.rooms.lock().expect("lock poisoned").get_mut(room) {
-            room.change_team(player, team);
-            self.channels
-                .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+    pub fn edit_team(
+        &self,
+        (room_id, player_id): PlayerRef,
+        team: TeamIdentifier,
+        name: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        room.edit_team(team, name, color)?;
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
+    }
+
+    pub fn set_player_role(
+        &self,
+        (room_id, player_id): PlayerRef,
+        account_id: &str,
+        role: PlayerRole,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Operator)?;
+        room.set_role(account_id, role)?;
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
+    }
+
+    pub fn change_team(
+        &self,
+        (room_id, player_id): PlayerRef,
+        team: TeamIdentifier,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.change_team(player_id, team)?;
+        self.update_team_channels(room);
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
+    }
+
+    // Keeps every member subscribed to the channel of their own team only
+    fn update_team_channels(&self, room: &GameRoom) {
+        let teams = room.teams();
+        for player in room.members() {
+            for team in &teams {
+                if player.team == Some(team.id) {
+                    self.channels
+                        .subscribe_handle(team.channel_id, &player.client);
+                } else {
+                    self.channels.unsubscribe(team.channel_id, player.client.id);
+                }
+            }
         }
     }
 
@@ -209,11 +407,12 @@ impl GameServer {
         let room = lock
             .get_mut(room_id)
             .ok_or(JoinRoomError::DoesNotExist(join_code.to_owned()))?;
-        let player_id = room.player_join(client, false)?;
+        let player_id = room.player_join(client, PlayerRole::Member)?;
         let channel = room.channel();
         self.channels
             .broadcast(channel, ServerEvent::RoomUpdate(room.status()));
         self.channels.subscribe(channel, client);
+        self.update_team_channels(room);
         Ok((
             (room_id, player_id),
             room.name().to_owned(),
@@ -222,15 +421,20 @@ impl GameServer {
         ))
     }
 
-    pub fn disconnect(&self, id: ClientId, player: PlayerRef) {
+    pub fn disconnect(self: &Arc<Self>, id: ClientId, player: PlayerRef) {
         self.client_removed(id, player, false);
     }
 
-    pub fn leave(&self, id: ClientId, player: PlayerRef) {
+    pub fn leave(self: &Arc<Self>, id: ClientId, player: PlayerRef) {
         self.client_removed(id, player, true);
     }
 
-    fn client_removed(&self, id: ClientId, (room_id, player): PlayerRef, explicit: bool) {
+    fn client_removed(
+        self: &Arc<Self>,
+        id: ClientId,
+        (room_id, player): PlayerRef,
+        explicit: bool,
+    ) {
         let mut lock = self.rooms.lock().expect("lock poisoned");
         if let Some(room) = lock.get_mut(room_id) {
             self.channels.unsubscribe(room.channel(), id);
@@ -239,87 +443,373 @@ impl GameServer {
                 self.channels.unsubscribe(team_channel, id);
             }
 
+            // Players dropping out of a running game keep their place until they reconnect
             if !explicit && room.has_started() {
+                if let Some(network_player) = room.set_disconnected(player, true) {
+                    self.channels.broadcast(
+                        room.channel(),
+                        ServerEvent::PresenceChange {
+                            player: network_player,
+                        },
+                    );
+                    tokio::spawn(self.clone().reconnect_timeout(
+                        room_id,
+                        player,
+                        room.config().reconnect_grace_period(),
+                    ));
+                }
                 return;
             }
 
-            let should_close = room.player_remove(player);
-            if should_close {
-                let room = lock.remove(room_id).expect("room exists");
-                self.channels.remove(room.channel());
-                for team in &room.teams() {
-                    self.channels.remove(team.channel_id);
-                }
-            } else {
+            self.remove_player(&mut lock, room_id, player);
+        }
+    }
+
+    async fn reconnect_timeout(
+        self: Arc<Self>,
+        room_id: RoomIdentifier,
+        player: PlayerIdentifier,
+        grace_period: Duration,
+    ) {
+        sleep(grace_period).await;
+
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        if let Some(room) = lock.get(room_id) {
+            // The player may have reconnected, or disconnected again since then
+            if !matches!(room.get_player(player), Some(p) if p.has_abandoned(grace_period)) {
+                return;
+            }
+            info!("player abandoned room {}", room.join_code());
+            self.remove_player(&mut lock, room_id, player);
+        }
+    }
+
+    fn remove_player(
+        &self,
+        rooms: &mut Arena<GameRoom>,
+        room_id: RoomIdentifier,
+        player: PlayerIdentifier,
+    ) {
+        let Some(room) = rooms.get_mut(room_id) else {
+            return;
+        };
+        if let Some(data) = room.get_player(player) {
+            data.client.detach();
+        }
+        let new_host = room.player_remove(player);
+        if room.is_empty() {
+            self.remove_room(rooms, room_id, CloseReason::Empty);
+        } else if room.is_abandoned() {
+            // Members who dropped out later keep their own grace period until then
+            self.remove_room(rooms, room_id, CloseReason::Abandoned);
+        } else {
+            if let Some(host) = new_host {
                 self.channels
-                    .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+                    .broadcast(room.channel(), ServerEvent::HostChanged { host });
             }
+            self.channels
+                .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        }
+    }
+
+    pub fn kick_player(
+        &self,
+        (room_id, player_id): PlayerRef,
+        account_id: &str,
+        ban: bool,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        let target = room.kick_player(player_id, account_id, ban)?;
+
+        self.channels.unsubscribe(room.channel(), target.client.id);
+        if let Some(team) = target.team.and_then(|t| room.get_team(t)) {
+            self.channels.unsubscribe(team.channel_id, target.client.id);
+        }
+        target.client.detach();
+        target.client.send(ServerEvent::Kicked { banned: ban });
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
+    }
+
+    pub fn close_room(&self, (room_id, player_id): PlayerRef) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Operator)?;
+        self.remove_room(&mut lock, room_id, CloseReason::ClosedByOperator);
+        Ok(())
+    }
+
+    // Members are notified before the room's channels are torn down
+    fn remove_room(
+        &self,
+        rooms: &mut Arena<GameRoom>,
+        room_id: RoomIdentifier,
+        reason: CloseReason,
+    ) {
+        let mut room = rooms.remove(room_id).expect("room exists");
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomClosed { reason });
+        // Maps of a grid that was never played can be given to other rooms
+        if matches!(room.state(), RoomState::Lobby | RoomState::LoadingMaps) {
+            self.maps
+                .extend_maps(room.config().selection, room.remove_all_maps());
+        }
+        room.detach_members();
+        self.channels.remove(room.channel());
+        for team in &room.teams() {
+            self.channels.remove(team.channel_id);
+        }
+    }
+
+    pub fn start_game(
+        self: &Arc<Self>,
+        (room_id, player_id): PlayerRef,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        self.transition(room, RoomState::Countdown)?;
+        room.next_round();
+        if room.config().randomize && room.config().play_mode == PlayMode::Teams {
+            room.shuffle_teams();
+            self.update_team_channels(room);
+            self.channels
+                .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
         }
+        tokio::spawn(self.clone().start_countdown(room_id));
+        Ok(())
     }
 
-    pub fn start_game(&self, (room_id, _player): PlayerRef) {
+    async fn start_countdown(self: Arc<Self>, room_id: RoomIdentifier) {
+        sleep(config::GAME_START_COUNTDOWN).await;
+
         let mut lock = self.rooms.lock().expect("lock poisoned");
         if let Some(room) = lock.get_mut(room_id) {
-            // TODO: check is operator
-            room.set_started(true);
+            if self.transition(room, RoomState::Running).is_err() {
+                return;
+            }
+            room.start_game();
             self.channels.broadcast(
                 room.channel(),
                 ServerEvent::GameStart {
                     maps: room.maps().clone(),
                 },
             );
+
+            if let Some(limit) = room.config().time_limit() {
+                tokio::spawn(self.clone().game_timer(room_id, room.round(), limit));
+            }
+        }
+    }
+
+    pub fn pause_game(&self, (room_id, player_id): PlayerRef) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        self.transition(room, RoomState::Paused)?;
+        if let Some(game) = room.game_data_mut() {
+            game.clock.pause();
+        }
+        Ok(())
+    }
+
+    pub fn resume_game(&self, (room_id, player_id): PlayerRef) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        self.transition(room, RoomState::Running)?;
+        if let Some(game) = room.game_data_mut() {
+            game.clock.resume();
+        }
+        Ok(())
+    }
+
+    async fn game_timer(self: Arc<Self>, room_id: RoomIdentifier, round: u32, limit: Duration) {
+        let mut warnings = config::TIME_LIMIT_WARNINGS
+            .into_iter()
+            .filter(|w| *w < limit)
+            .collect::<Vec<Duration>>()
+            .into_iter()
+            .peekable();
+        loop {
+            let wait = {
+                let mut lock = self.rooms.lock().expect("lock poisoned");
+                let room = match lock.get_mut(room_id) {
+                    Some(room) => room,
+                    None => return,
+                };
+
+                // Stop if the game this timer was started for is over
+                if room.round() != round
+                    || !matches!(room.state(), RoomState::Running | RoomState::Paused)
+                {
+                    return;
+                }
+
+                let Some(game) = room.game_data() else {
+                    return;
+                };
+                let elapsed = game.clock.elapsed();
+                let remaining = limit.saturating_sub(elapsed);
+                if remaining.is_zero() {
+                    self.end_game_by_time(room);
+                    return;
+                }
+
+                let mut passed = None;
+                while let Some(warning) = warnings.next_if(|w| *w >= remaining) {
+                    passed = Some(warning);
+                }
+                if let Some(warning) = passed {
+                    self.channels.broadcast(
+                        room.channel(),
+                        ServerEvent::TimeLimitWarning {
+                            seconds_left: warning.as_secs(),
+                        },
+                    );
+                }
+
+                remaining - warnings.peek().copied().unwrap_or_default()
+            };
+            sleep(wait).await;
         }
     }
 
+    fn end_game_by_time(&self, room: &mut GameRoom) {
+        let victory = room.game_data().as_ref().and_then(|game| {
+            game.leader().map(|owner| Victory {
+                owner,
+                lines: Vec::new(),
+            })
+        });
+        info!(
+            "time is up in room {}, winner: {:?}",
+            room.join_code(),
+            victory.as_ref().map(|v| &v.owner)
+        );
+        self.end_game(room, victory);
+    }
+
+    fn end_game(&self, room: &mut GameRoom, victory: Option<Victory>) {
+        if let Err(e) = self.transition(room, RoomState::Ended) {
+            warn!("failed to end game in room {}: {}", room.join_code(), e);
+            return;
+        }
+        if let Some(victory) = &victory {
+            room.record_win(&victory.owner);
+        }
+        if let Some(game) = room.game_data() {
+            self.channels
+                .broadcast(room.channel(), ServerEvent::GameEnd(game.summary(victory)));
+        }
+    }
+
+    pub fn rematch(
+        self: &Arc<Self>,
+        (room_id, player_id): PlayerRef,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        room.check_role(player_id, PlayerRole::Moderator)?;
+        self.transition(room, RoomState::LoadingMaps)?;
+
+        room.reset_game();
+        let old_maps = room.remove_all_maps();
+        let config = room.config();
+        tokio::spawn(self.clone().replace_maps(
+            room_id,
+            MapQuery::new(config.selection, config.cell_count(), config.mappack_id),
+            old_maps,
+        ));
+        self.channels
+            .broadcast(room.channel(), ServerEvent::RoomUpdate(room.status()));
+        Ok(())
+    }
+
     pub fn claim_cell(
         &self,
         (room_id, player_id): PlayerRef,
         map_uid: String,
         time: u64,
         medal: Medal,
-    ) {
+    ) -> Result<(), ClaimError> {
         let mut lock = self.rooms.lock().expect("lock poisoned");
-        if let Some(room) = lock.get_mut(room_id) {
-            if !room.has_started() {
-                return;
-            }
-            if let Some(player) = room.get_player(player_id) {
-                let claim = MapClaim {
-                    player: NetworkPlayer::from(player),
-                    time,
-                    medal,
-                };
-
-                let cell_id = room.get_map(map_uid).unwrap().0; // TODO: avoid unwrap
-                let cell = room
-                    .get_cell_record(cell_id)
-                    .expect("cells are correctly initialized");
-
-                // unwrap here is safe because we check for none
-                if cell.claim.is_none() || claim.time < cell.claim.as_ref().unwrap().time {
-                    cell.claim = Some(claim.clone());
+        let room = lock.get_mut(room_id).ok_or(ClaimError::NotInRoom)?;
+        if room.state() != RoomState::Running {
+            return Err(ClaimError::NotRunning);
+        }
+        if !medal.is_at_least(room.config().medal) {
+            return Err(ClaimError::MedalRequirement);
+        }
+        let player = room.get_player(player_id).ok_or(ClaimError::NotInRoom)?;
+        let claim = MapClaim {
+            player: NetworkPlayer::from(player),
+            time,
+            medal,
+        };
+
+        let (cell_id, map) = room.get_map(map_uid).ok_or(ClaimError::UnknownMap)?;
+        if matches!(map.medal_time(medal), Some(limit) if time > limit) {
+            return Err(ClaimError::InvalidTime);
+        }
+        let cell = room.get_cell(cell_id).ok_or(ClaimError::UnknownMap)?;
 
+        // The claim policy only restricts steals, owners can always improve their own time
+        if let Some(current) = &cell.claim {
+            let steal = room.is_steal(cell_id, &claim);
+            let policy = if steal {
+                room.config().claim_policy
+            } else {
+                ClaimPolicy::StealByTime
+            };
+            if let Err(reason) = claim.can_replace(current, policy) {
+                if steal {
                     self.channels.broadcast(
                         room.channel(),
-                        ServerEvent::CellClaim {
-                            cell_id: cell_id,
-                            claim: claim,
+                        ServerEvent::CellClaimRefused {
+                            cell_id,
+                            claim,
+                            reason,
                         },
                     );
-
-                    let bingos = room.check_for_bingos();
-                    if bingos.len() > 0 {
-                        self.channels.broadcast(
-                            room.channel(),
-                            ServerEvent::AnnounceBingo {
-                                line: bingos[0].clone(),
-                            },
-                        );
-                        room.set_started(false);
-                    }
                 }
+                return Err(reason);
             }
         }
+
+        room.claim_cell(cell_id, claim.clone());
+        self.channels.broadcast(
+            room.channel(),
+            ServerEvent::CellClaim {
+                cell_id: cell_id,
+                claim: claim,
+            },
+        );
+
+        if let Some(victory) = room.check_win() {
+            self.end_game(room, Some(victory));
+        }
+        Ok(())
+    }
+
+    pub fn send_chat(
+        &self,
+        (room_id, player_id): PlayerRef,
+        content: &str,
+        team_only: bool,
+    ) -> Result<(), RoomStateError> {
+        let mut lock = self.rooms.lock().expect("lock poisoned");
+        let room = lock.get_mut(room_id).ok_or(RoomStateError::RoomNotFound)?;
+        let message = room.post_chat(player_id, content, team_only)?;
+        let channel = match message.team.and_then(|team| room.get_team(team)) {
+            Some(team) => team.channel_id,
+            None => room.channel(),
+        };
+        self.channels
+            .broadcast(channel, ServerEvent::ChatMessage(message));
+        Ok(())
     }
 
     pub fn sync_client(&self, (room_id, player_id): PlayerRef) -> Option<SyncPacket> {
@@ -331,7 +821,12 @@ impl GameServer {
     }
 
     pub fn resubscribe_client(&self, client: &GameClient, (room, player): PlayerRef) {
-        if let Some(room) = self.rooms.lock().expect("lock poisoned").get(room) {
+        if let Some(room) = self.rooms.lock().expect("lock poisoned").get_mut(room) {
+            room.rebind_player(player, client);
+            if let Some(player) = room.set_disconnected(player, false) {
+                self.channels
+                    .broadcast(room.channel(), ServerEvent::PresenceChange { player });
+            }
             self.channels.subscribe(room.channel(), client);
             if let Some(team) = room.get_player(player).and_then(|p| p.team) {
                 let team_channel = room
@@ -343,14 +838,50 @@ impl GameServer {
         }
     }
 
-    pub fn try_reconnect(&self, identity: &PlayerIdentity) -> Option<PlayerRef> {
-        let rooms = self.rooms.lock().expect("lock poisoned");
-        for (room_id, room) in rooms.iter() {
-            let id_result = room.identify_player(identity);
-            if let Some(player_id) = id_result {
-                return Some((room_id, player_id));
+    // A new login takes over the previous session of the account, along with its place in a room.
+    // Returns: whether the client has a room to reconnect to, and the generation of its session
+    pub fn open_session(&self, client: &GameClient) -> (bool, u64) {
+        let handle = client.handle();
+        let (previous, generation) = self
+            .sessions
+            .open(&client.identity().account_id, handle.clone());
+        let membership = handle.membership();
+        let Some(previous) = previous.filter(|p| p.connected) else {
+            return (membership.is_some(), generation);
+        };
+
+        info!(
+            "{} logged in again, closing their previous connection",
+            client.identity().display_name
+        );
+        if let Some((room_id, player)) = membership {
+            if let Some(room) = self.rooms.lock().expect("lock poisoned").get(room_id) {
+                self.channels
+                    .unsubscribe(room.channel(), previous.client.id);
+                if let Some(team) = room
+                    .get_player(player)
+                    .and_then(|p| p.team)
+                    .and_then(|t| room.get_team(t))
+                {
+                    self.channels
+                        .unsubscribe(team.channel_id, previous.client.id);
+                }
             }
         }
-        return None;
+        previous.client.send_and_close(ServerEvent::Displaced {
+            reason: DisplacedReason::LoggedInElsewhere,
+        });
+        (membership.is_some(), generation)
+    }
+
+    // Resume tokens of replaced sessions are revoked, including those of displaced connections
+    pub fn is_current_session(&self, claims: &ResumeClaims) -> bool {
+        self.sessions
+            .is_current_generation(&claims.account_id, claims.generation)
+    }
+
+    pub fn close_session(&self, client: &GameClient) {
+        self.sessions
+            .close(&client.identity().account_id, client.get_id());
     }
 }
@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_repr::Serialize_repr;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    client::{ClientHandle, ClientId},
+    config::RESUME_TOKEN_LIFETIME,
+    rest::auth::PlayerIdentity,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Issues and checks the tokens that let a player reconnect without going through Openplanet again.
+// The signing key lives only as long as the process, which is also the lifetime of every room.
+pub struct ResumeTokens {
+    key: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ResumeClaims {
+    pub account_id: String,
+    pub display_name: String,
+    // Session generation of the account when the token was issued, see `SessionRegistry`
+    pub generation: u64,
+    // Seconds since the UNIX epoch
+    pub expires: u64,
+}
+
+impl ResumeClaims {
+    pub fn identity(&self) -> PlayerIdentity {
+        PlayerIdentity {
+            account_id: self.account_id.clone(),
+            display_name: self.display_name.clone(),
+        }
+    }
+}
+
+impl ResumeTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Tokens issued to a resumed session keep the expiry of the token it was resumed with,
+    // so that resuming does not extend a token's lifetime indefinitely
+    pub fn issue(
+        &self,
+        identity: &PlayerIdentity,
+        generation: u64,
+        expires: Option<u64>,
+    ) -> String {
+        self.issue_at(identity, generation, expires, unix_time())
+    }
+
+    pub fn verify(&self, token: &str) -> Result<ResumeClaims, ResumeTokenError> {
+        self.verify_at(token, unix_time())
+    }
+
+    fn issue_at(
+        &self,
+        identity: &PlayerIdentity,
+        generation: u64,
+        expires: Option<u64>,
+        now: u64,
+    ) -> String {
+        let claims = ResumeClaims {
+            account_id: identity.account_id.clone(),
+            display_name: identity.display_name.clone(),
+            generation,
+            expires: expires.unwrap_or(now + RESUME_TOKEN_LIFETIME.as_secs()),
+        };
+        let payload = serde_json::to_vec(&claims).expect("claims serialization");
+        let signature = self.mac(&payload).finalize().into_bytes();
+        format!("{}.{}", to_hex(&payload), to_hex(&signature))
+    }
+
+    fn verify_at(&self, token: &str, now: u64) -> Result<ResumeClaims, ResumeTokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(ResumeTokenError::Malformed)?;
+        let payload = from_hex(payload).ok_or(ResumeTokenError::Malformed)?;
+        let signature = from_hex(signature).ok_or(ResumeTokenError::Malformed)?;
+        self.mac(&payload)
+            .verify_slice(&signature)
+            .map_err(|_| ResumeTokenError::BadSignature)?;
+
+        let claims: ResumeClaims =
+            serde_json::from_slice(&payload).map_err(|_| ResumeTokenError::Malformed)?;
+        if claims.expires <= now {
+            return Err(ResumeTokenError::Expired);
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(payload);
+        mac
+    }
+}
+
+impl Default for ResumeTokens {
+    fn default() -> Self {
+        Self {
+            key: rand::thread_rng().gen(),
+        }
+    }
+}
+
+// The latest connection of every account, so that a player is never controlled from two sockets.
+// Sessions outlive their connection while the player still has a place in a room.
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<String, Session>>,
+    // Every session opened for an account starts a new generation, only resume tokens of the
+    // latest one are accepted. Entries are kept until all tokens they could accept have expired.
+    generations: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+pub struct Session {
+    pub client: ClientHandle,
+    pub connected: bool,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            generations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The new client takes the place in a room of the session it replaces, both happen at once
+    // so that simultaneous logins can't end up with the same player.
+    // Returns: the session that was replaced by this one, and the generation of the new session
+    pub fn open(&self, account_id: &str, client: ClientHandle) -> (Option<Session>, u64) {
+        let mut sessions = self.sessions.lock().expect("lock poisoned");
+        let mut generations = self.generations.lock().expect("lock poisoned");
+        let generation = generations.get(account_id).map_or(0, |(g, _)| g + 1);
+        generations.insert(account_id.to_owned(), (generation, Instant::now()));
+
+        let previous = sessions.insert(
+            account_id.to_owned(),
+            Session {
+                client: client.clone(),
+                connected: true,
+            },
+        );
+        if let Some(previous) = &previous {
+            client.set_membership(previous.client.membership());
+            previous.client.detach();
+        }
+        (previous, generation)
+    }
+
+    pub fn is_current_generation(&self, account_id: &str, generation: u64) -> bool {
+        matches!(
+            self.generations.lock().expect("lock poisoned").get(account_id),
+            Some((current, _)) if *current == generation
+        )
+    }
+
+    // Does nothing if the account has logged in again from another connection since then
+    pub fn close(&self, account_id: &str, client: ClientId) {
+        let mut sessions = self.sessions.lock().expect("lock poisoned");
+        let Some(session) = sessions.get_mut(account_id) else {
+            return;
+        };
+        if session.client.id != client {
+            return;
+        }
+        if session.client.membership().is_some() {
+            session.connected = false;
+        } else {
+            sessions.remove(account_id);
+        }
+    }
+
+    // Forgets disconnected players who have since lost their place in the room
+    pub fn prune(&self) {
+        self.sessions
+            .lock()
+            .expect("lock poisoned")
+            .retain(|_, session| session.connected || session.client.membership().is_some());
+        self.generations
+            .lock()
+            .expect("lock poisoned")
+            .retain(|_, (_, opened)| opened.elapsed() < RESUME_TOKEN_LIFETIME);
+    }
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize_repr, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum DisplacedReason {
+    // The same account logged in from another connection, which took over the session
+    LoggedInElsewhere,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time after the UNIX epoch")
+        .as_secs()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some((hex_digit(*high)? << 4) | hex_digit(*low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|d| d as u8)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ResumeTokenError {
+    #[error("resume token is malformed")]
+    Malformed,
+    #[error("resume token signature does not match")]
+    BadSignature,
+    #[error("resume token has expired")]
+    Expired,
+    #[error("resume token belongs to a session that was replaced")]
+    Revoked,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn identity() -> PlayerIdentity {
+        PlayerIdentity {
+            account_id: "0a1b2c3d".to_owned(),
+            display_name: "Player".to_owned(),
+        }
+    }
+
+    #[test]
+    fn check_resume_token() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.issue_at(&identity(), 3, None, 1000);
+        let claims = tokens.verify_at(&token, 1000).unwrap();
+        assert_eq!(claims.identity(), identity());
+        assert_eq!(claims.generation, 3);
+
+        // Resumed sessions keep the original expiry
+        let expiry = 1000 + RESUME_TOKEN_LIFETIME.as_secs();
+        assert_eq!(claims.expires, expiry);
+        let resumed = tokens.issue_at(&identity(), 4, Some(claims.expires), expiry - 1);
+        assert_eq!(
+            tokens.verify_at(&resumed, expiry),
+            Err(ResumeTokenError::Expired)
+        );
+        assert_eq!(
+            tokens.verify_at(&token, expiry),
+            Err(ResumeTokenError::Expired)
+        );
+
+        // Tokens from another process are rejected
+        assert_eq!(
+            ResumeTokens::new().verify_at(&token, 1000),
+            Err(ResumeTokenError::BadSignature)
+        );
+
+        // Altering the claims invalidates the signature
+        let (payload, signature) = token.split_once('.').unwrap();
+        let forged = String::from_utf8(from_hex(payload).unwrap())
+            .unwrap()
+            .replace("0a1b2c3d", "ffffffff");
+        let forged = format!("{}.{}", to_hex(forged.as_bytes()), signature);
+        assert_eq!(
+            tokens.verify_at(&forged, 1000),
+            Err(ResumeTokenError::BadSignature)
+        );
+
+        assert_eq!(
+            tokens.verify_at("not a token", 1000),
+            Err(ResumeTokenError::Malformed)
+        );
+    }
+
+    fn is_connected(registry: &SessionRegistry, account_id: &str) -> Option<bool> {
+        registry
+            .sessions
+            .lock()
+            .unwrap()
+            .get(account_id)
+            .map(|session| session.connected)
+    }
+
+    #[tokio::test]
+    async fn check_session_takeover() {
+        let registry = SessionRegistry::new();
+        let first = ClientHandle::for_test(1).await;
+        let player = (
+            generational_arena::Index::from_raw_parts(0, 0),
+            generational_arena::Index::from_raw_parts(1, 0),
+        );
+        first.set_membership(Some(player));
+        assert!(registry.open("account", first.clone()).0.is_none());
+
+        // The new client takes over the place in the room
+        let second = ClientHandle::for_test(2).await;
+        let previous = registry.open("account", second.clone()).0.unwrap();
+        assert_eq!(previous.client.id, 1);
+        assert!(previous.connected);
+        assert_eq!(first.membership(), None);
+        assert_eq!(second.membership(), Some(player));
+
+        // The replaced client disconnecting does not affect the new session
+        registry.close("account", 1);
+        assert_eq!(is_connected(&registry, "account"), Some(true));
+
+        // Players with a place in a room keep their session while disconnected
+        registry.close("account", 2);
+        assert_eq!(is_connected(&registry, "account"), Some(false));
+        registry.prune();
+        assert_eq!(is_connected(&registry, "account"), Some(false));
+
+        // Reconnecting after a drop is not a takeover
+        let third = ClientHandle::for_test(3).await;
+        let (previous, generation) = registry.open("account", third.clone());
+        assert!(!previous.unwrap().connected);
+        assert_eq!(third.membership(), Some(player));
+
+        // Only the resume token of the latest session is accepted
+        assert_eq!(generation, 2);
+        assert!(registry.is_current_generation("account", 2));
+        assert!(!registry.is_current_generation("account", 1));
+        assert!(!registry.is_current_generation("other", 0));
+    }
+
+    #[tokio::test]
+    async fn check_session_cleanup() {
+        let registry = SessionRegistry::new();
+        let client = ClientHandle::for_test(1).await;
+        registry.open("lobby", client.clone());
+        registry.close("lobby", 1);
+        assert_eq!(is_connected(&registry, "lobby"), None);
+
+        let player = (
+            generational_arena::Index::from_raw_parts(0, 0),
+            generational_arena::Index::from_raw_parts(1, 0),
+        );
+        let dropped = ClientHandle::for_test(2).await;
+        dropped.set_membership(Some(player));
+        registry.open("dropped", dropped.clone());
+        registry.open("online", ClientHandle::for_test(3).await);
+        registry.close("dropped", 2);
+
+        // Sessions are forgotten once the player loses their place, connected ones are kept
+        dropped.detach();
+        registry.prune();
+        assert_eq!(is_connected(&registry, "dropped"), None);
+        assert_eq!(is_connected(&registry, "online"), Some(true));
+    }
+}
@@ -1,9 +1,10 @@
 use serde::Serialize;
 
 use crate::{
+    chat::ChatMessage,
     gamedata::ActiveGameData,
     gamemap::GameMap,
-    gameroom::{GameRoom, PlayerIdentifier, RoomConfiguration, RoomStatus},
+    gameroom::{GameRoom, PlayerIdentifier, PlayerRole, RoomConfiguration, RoomState, RoomStatus},
 };
 
 #[derive(Serialize)]
@@ -12,20 +13,24 @@ pub struct SyncPacket {
     join_code: String,
     host: bool,
     config: RoomConfiguration,
+    state: RoomState,
     status: RoomStatus,
     maps: Vec<GameMap>,
     #[serde(skip_serializing_if = "Option::is_none")]
     game_data: Option<ActiveGameData>,
+    chat: Vec<ChatMessage>,
 }
 
 pub fn build_sync_packet(room: &mut GameRoom, player_id: PlayerIdentifier) -> Option<SyncPacket> {
     room.get_player(player_id).map(|player| SyncPacket {
         room_name: room.name().to_string(),
         join_code: room.join_code().to_string(),
-        host: player.operator,
+        host: player.role == PlayerRole::Operator,
         config: room.config().clone(),
+        state: room.state(),
         status: room.status(),
         maps: room.maps().clone(),
         game_data: room.game_data().clone(),
+        chat: room.chat_history(player.team),
     })
 }
@@ -11,6 +11,9 @@ impl RgbColor {
         if size != 6 {
             return Err(ParseColorError::LengthError(size));
         }
+        if !col.is_ascii() {
+            return Err(ParseColorError::NotHexadecimal);
+        }
         let r = u8::from_str_radix(&col[0..2], 16)?;
         let g = u8::from_str_radix(&col[2..4], 16)?;
         let b = u8::from_str_radix(&col[4..6], 16)?;
@@ -26,6 +29,88 @@ impl RgbColor {
     pub fn blue(&self) -> u8 {
         self.2
     }
+
+    // Hue in degrees, saturation and lightness between 0 and 1
+    pub fn from_hsl(hue: f64, saturation: f64, lightness: f64) -> Self {
+        let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+        let sector = hue.rem_euclid(360.) / 60.;
+        let x = chroma * (1. - (sector % 2. - 1.).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let m = lightness - chroma / 2.;
+        let channel = |v: f64| ((v + m) * 255.).round() as u8;
+        Self(channel(r), channel(g), channel(b))
+    }
+
+    // As defined by WCAG 2
+    pub fn relative_luminance(&self) -> f64 {
+        let linear = |c: u8| {
+            let c = c as f64 / 255.;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+
+    pub fn contrast_ratio(&self, other: &RgbColor) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    // Perceived difference between two colors, using the "redmean" approximation
+    pub fn distance(&self, other: &RgbColor) -> f64 {
+        let red_mean = (self.0 as f64 + other.0 as f64) / 2.;
+        let dr = self.0 as f64 - other.0 as f64;
+        let dg = self.1 as f64 - other.1 as f64;
+        let db = self.2 as f64 - other.2 as f64;
+        ((2. + red_mean / 256.) * dr * dr
+            + 4. * dg * dg
+            + (2. + (255. - red_mean) / 256.) * db * db)
+            .sqrt()
+    }
+}
+
+// Picks the color that stands out the most from the existing ones, among hues spread by the
+// golden angle, as long as it is at least `min_distance` away and readable on `background`.
+// Saturation and lightness are varied as well, so that a full room of teams still finds colors.
+pub fn generate_distinct(
+    existing: &[RgbColor],
+    min_distance: f64,
+    background: RgbColor,
+    min_contrast: f64,
+) -> Option<RgbColor> {
+    const GOLDEN_ANGLE: f64 = 137.507_764;
+    let distance_to_existing = |color: &RgbColor| {
+        existing
+            .iter()
+            .map(|other| color.distance(other))
+            .fold(f64::INFINITY, f64::min)
+    };
+
+    (0..128)
+        .flat_map(|i| {
+            [0.8, 0.5, 1., 0.25]
+                .into_iter()
+                .flat_map(move |saturation| {
+                    [0.55, 0.45, 0.65, 0.35, 0.75, 0.85].map(|lightness| {
+                        RgbColor::from_hsl(i as f64 * GOLDEN_ANGLE, saturation, lightness)
+                    })
+                })
+        })
+        .filter(|color| color.contrast_ratio(&background) >= min_contrast)
+        .map(|color| (color, distance_to_existing(&color)))
+        .filter(|(_, distance)| *distance >= min_distance)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(color, _)| color)
 }
 
 #[derive(Error, Debug)]
@@ -33,6 +118,9 @@ pub enum ParseColorError {
     #[error("Expected string of length 6, got {0} instead")]
     LengthError(usize),
 
+    #[error("Expected a hexadecimal string")]
+    NotHexadecimal,
+
     #[error(transparent)]
     ParseIntError(#[from] ParseIntError),
 }
@@ -46,5 +134,42 @@ mod test {
         let green = RgbColor(0, 255, 0);
         let green_hex = RgbColor::from_hex("00FF00").unwrap();
         assert_eq!(green, green_hex);
+        assert!(RgbColor::from_hex("1é345").is_err());
+    }
+
+    #[test]
+    fn check_color_hsl() {
+        assert_eq!(RgbColor::from_hsl(0., 1., 0.5), RgbColor(255, 0, 0));
+        assert_eq!(RgbColor::from_hsl(120., 1., 0.5), RgbColor(0, 255, 0));
+        assert_eq!(RgbColor::from_hsl(240., 1., 0.25), RgbColor(0, 0, 128));
+        assert_eq!(RgbColor::from_hsl(0., 0., 1.), RgbColor(255, 255, 255));
+    }
+
+    #[test]
+    fn check_contrast_ratio() {
+        let black = RgbColor(0, 0, 0);
+        let white = RgbColor(255, 255, 255);
+        assert!((black.contrast_ratio(&white) - 21.).abs() < 0.01);
+        assert_eq!(white.contrast_ratio(&white), 1.);
+    }
+
+    #[test]
+    fn check_generate_distinct() {
+        let background = RgbColor(0, 0, 0);
+        let mut colors = Vec::new();
+        for _ in 0..16 {
+            let color = generate_distinct(&colors, 60., background, 3.).unwrap();
+            assert!(colors.iter().all(|c| c.distance(&color) >= 60.));
+            assert!(color.contrast_ratio(&background) >= 3.);
+            colors.push(color);
+        }
+    }
+
+    #[test]
+    fn check_color_distance() {
+        let red = RgbColor::from_hex("F81315").unwrap();
+        assert_eq!(red.distance(&red), 0.);
+        assert!(red.distance(&RgbColor::from_hex("F01518").unwrap()) < 30.);
+        assert!(red.distance(&RgbColor::from_hex("E04980").unwrap()) > 100.);
     }
 }